@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{
+    cli::BatchArgs,
+    export::{self, ExportFormat, ExportOptions, ExportSettings},
+    headless::Headless,
+    loader,
+    program::Program,
+    settings::Settings,
+};
+
+/// Processes every input on an offscreen device and prints a line per file.
+/// Returns whether all of them succeeded.
+#[allow(clippy::print_stdout)]
+pub fn run(args: &BatchArgs) -> crate::Result<bool> {
+    let preset = args
+        .preset
+        .as_deref()
+        .map(Settings::load)
+        .transpose()?
+        .unwrap_or_default();
+    let files: Vec<PathBuf> = args
+        .inputs
+        .iter()
+        .flat_map(|input| {
+            if input.is_dir() {
+                loader::folder_images(input)
+            } else {
+                vec![input.clone()]
+            }
+        })
+        .collect();
+    let outputs: Vec<PathBuf> = files
+        .iter()
+        .enumerate()
+        .map(|(index, path)| output_path(args, path, index))
+        .collect();
+    if let Some((first, second, output)) = first_collision(&files, &outputs) {
+        return Err(format!(
+            "{} and {} would both be written to {}, add {{index}} to the template",
+            first.display(),
+            second.display(),
+            output.display()
+        )
+        .into());
+    }
+    let headless = Headless::new()?;
+    println!(
+        "Processing {} files on {}",
+        files.len(),
+        headless.adapter_name()
+    );
+
+    let start = Instant::now();
+    let mut failed = 0;
+    for (index, (path, output)) in files.iter().zip(&outputs).enumerate() {
+        let file_start = Instant::now();
+        let progress = format!("[{}/{}]", index + 1, files.len());
+        match process(&headless, args, &preset, path, output) {
+            Ok(()) => println!(
+                "{progress} {} -> {} ({:.2}s)",
+                path.display(),
+                output.display(),
+                file_start.elapsed().as_secs_f32()
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("{progress} {} failed: {e}", path.display());
+            }
+        }
+    }
+    println!(
+        "Processed {} files in {:.2}s, {failed} failed",
+        files.len(),
+        start.elapsed().as_secs_f32()
+    );
+    Ok(failed == 0)
+}
+
+fn process(
+    headless: &Headless,
+    args: &BatchArgs,
+    preset: &Settings,
+    path: &Path,
+    output: &Path,
+) -> crate::Result<()> {
+    let decoded = loader::decode_image(path)?;
+    let mut program = Program::default();
+    program.set_image(&decoded);
+    // Edits made to this file take precedence over the preset
+    if decoded.sidecar.is_none() {
+        program.settings = preset.clone();
+    }
+    // Rather than exporting without the LUT the edits ask for
+    program.load_lut()?;
+
+    if let Some(folder) = output.parent() {
+        std::fs::create_dir_all(folder)?;
+    }
+    let options = ExportOptions {
+        path: output.to_path_buf(),
+        format: args.format,
+        settings: ExportSettings {
+            bit_depth: args.bit_depth,
+            jpeg_quality: args.jpeg_quality,
+        },
+    };
+    export::export(headless, &program, &options)
+}
+
+fn output_path(args: &BatchArgs, path: &Path, index: usize) -> PathBuf {
+    let folder = args
+        .output
+        .clone()
+        .unwrap_or_else(|| loader::parent_folder(path).join("processed"));
+    folder.join(output_name(&args.template, path, index, args.format))
+}
+
+/// Two inputs that would be written to the same output, which happens when
+/// files with the same name from different folders share an output folder
+fn first_collision<'a>(
+    files: &'a [PathBuf],
+    outputs: &'a [PathBuf],
+) -> Option<(&'a Path, &'a Path, &'a Path)> {
+    let mut seen = HashMap::new();
+    files.iter().zip(outputs).find_map(|(file, output)| {
+        seen.insert(output, file)
+            .map(|first| (first.as_path(), file.as_path(), output.as_path()))
+    })
+}
+
+// The placeholders of the template look like format arguments
+#[allow(clippy::literal_string_with_formatting_args)]
+fn output_name(template: &str, path: &Path, index: usize, format: ExportFormat) -> String {
+    let name = path
+        .file_stem()
+        .map_or_else(|| "image".into(), |stem| stem.to_string_lossy());
+    template
+        .replace("{name}", &name)
+        .replace("{index}", &format!("{:04}", index + 1))
+        .replace("{ext}", format.extensions()[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_name() {
+        let path = Path::new("shoot/IMG_0042.CR2");
+        assert_eq!(
+            output_name("{name}.{ext}", path, 0, ExportFormat::Jpeg),
+            "IMG_0042.jpg"
+        );
+        assert_eq!(
+            output_name("wedding-{index}-{name}.{ext}", path, 11, ExportFormat::Tiff),
+            "wedding-0012-IMG_0042.tif"
+        );
+    }
+
+    #[test]
+    fn test_first_collision() {
+        let files = [
+            PathBuf::from("day1/IMG_0001.CR2"),
+            PathBuf::from("day1/IMG_0002.CR2"),
+            PathBuf::from("day2/IMG_0001.CR2"),
+        ];
+        let outputs = files
+            .iter()
+            .map(|file| {
+                Path::new("out").join(output_name("{name}.{ext}", file, 0, ExportFormat::Jpeg))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            first_collision(&files, &outputs),
+            Some((
+                files[0].as_path(),
+                files[2].as_path(),
+                Path::new("out/IMG_0001.jpg")
+            ))
+        );
+        assert_eq!(first_collision(&files[..2], &outputs[..2]), None);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::program::Decoded;
+
+/// Enough for a handful of full size raws. Images are cached as decoded,
+/// which for raws is two bytes a sample rather than the four of the float
+/// texture they are uploaded to.
+pub const DEFAULT_BUDGET: usize = 2 * 1024 * 1024 * 1024;
+
+#[derive(Debug)]
+struct Entry {
+    path: PathBuf,
+    modified: SystemTime,
+    decoded: Arc<Decoded>,
+    size: usize,
+}
+
+/// Decoded images keyed by path and modification time, evicting the least
+/// recently used ones once they take up more than the memory budget.
+#[derive(Debug)]
+pub struct ImageCache {
+    /// Ordered from least to most recently used
+    entries: Vec<Entry>,
+    budget: usize,
+}
+
+impl Default for ImageCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET)
+    }
+}
+
+impl ImageCache {
+    pub const fn new(budget: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            budget,
+        }
+    }
+
+    /// Looks up the image and marks it as the most recently used. An entry
+    /// for an older version of the file is dropped.
+    pub fn get(&mut self, path: &Path, modified: SystemTime) -> Option<Arc<Decoded>> {
+        let index = self.entries.iter().position(|e| e.path == path)?;
+        let entry = self.entries.remove(index);
+        if entry.modified != modified {
+            return None;
+        }
+        let decoded = entry.decoded.clone();
+        self.entries.push(entry);
+        Some(decoded)
+    }
+
+    /// The modification time of the cached version of the file, if any
+    pub fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.entries
+            .iter()
+            .find(|e| e.path == path)
+            .map(|e| e.modified)
+    }
+
+    /// Inserts the image as the most recently used one. The newest image is
+    /// kept even if it alone is over the budget.
+    pub fn insert(&mut self, modified: SystemTime, decoded: Arc<Decoded>) {
+        self.entries.retain(|e| e.path != decoded.path);
+        self.entries.push(Entry {
+            path: decoded.path.clone(),
+            modified,
+            size: decoded.image.memory_size(),
+            decoded,
+        });
+        while self.entries.len() > 1 && self.memory_size() > self.budget {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+}
+
+/// The modification time the cache is keyed by
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+#[allow(clippy::cognitive_complexity)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{metadata::Metadata, orientation::Orientation, program::Image};
+
+    fn decoded(path: &str, width: u32) -> Arc<Decoded> {
+        Arc::new(Decoded {
+            path: path.into(),
+            image: Arc::new(Image::DynamicImage(image::DynamicImage::new_rgba8(
+                width, 1,
+            ))),
+            orientation: Orientation::Normal,
+            sidecar: None,
+            metadata: Metadata::default(),
+        })
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let time = SystemTime::UNIX_EPOCH;
+        let mut cache = ImageCache::new(30);
+        cache.insert(time, decoded("a", 3));
+        cache.insert(time, decoded("b", 3));
+        assert!(cache.get(Path::new("a"), time).is_some());
+        cache.insert(time, decoded("c", 3));
+
+        assert_eq!(cache.modified(Path::new("a")), Some(time));
+        assert_eq!(cache.modified(Path::new("b")), None);
+        assert_eq!(cache.modified(Path::new("c")), Some(time));
+        assert_eq!(cache.memory_size(), 24);
+    }
+
+    #[test]
+    fn test_modified_file_misses() {
+        let time = SystemTime::UNIX_EPOCH;
+        let mut cache = ImageCache::default();
+        cache.insert(time, decoded("a", 1));
+        let later = time + Duration::from_secs(1);
+        assert!(cache.get(Path::new("a"), later).is_none());
+        assert_eq!(cache.memory_size(), 0);
+    }
+}
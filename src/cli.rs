@@ -0,0 +1,130 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::export::{BitDepth, ExportFormat};
+
+const MIB: usize = 1024 * 1024;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "View camera raws and images with GPU processing")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Image file to open, or a folder to browse
+    pub path: Option<PathBuf>,
+
+    /// Initial window width
+    #[arg(long, default_value_t = 1024.0)]
+    pub width: f32,
+
+    /// Initial window height
+    #[arg(long, default_value_t = 1024.0)]
+    pub height: f32,
+
+    /// Graphics API to pick the GPU adapter from
+    #[arg(long, value_enum, global = true)]
+    pub backend: Option<Backend>,
+
+    /// Whether to prefer the discrete or the integrated GPU
+    #[arg(long, value_enum, global = true)]
+    pub power_preference: Option<PowerPreference>,
+
+    /// Memory budget of the decoded image cache, in MiB
+    #[arg(long, default_value_t = 2048)]
+    pub cache_size: usize,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Process images without opening a window
+    Batch(BatchArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BatchArgs {
+    /// Image files, or folders of images, to process
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Folder for the processed images, a `processed` folder next to each
+    /// input by default
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Settings file to apply to images that don't have a sidecar
+    #[arg(long)]
+    pub preset: Option<PathBuf>,
+
+    /// Name of each output file, where `{name}` is the input file name
+    /// without extension, `{index}` its position in the batch and `{ext}`
+    /// the extension of the output format
+    #[arg(long, default_value = "{name}.{ext}")]
+    pub template: String,
+
+    #[arg(long, value_enum, default_value_t = ExportFormat::Jpeg)]
+    pub format: ExportFormat,
+
+    #[arg(long, value_enum, default_value_t = BitDepth::Eight)]
+    pub bit_depth: BitDepth,
+
+    #[arg(long, default_value_t = 90)]
+    pub jpeg_quality: u8,
+
+    /// Name, or part of the name, of the GPU adapter to use, such as
+    /// `llvmpipe` for software rendering
+    #[arg(long)]
+    pub adapter: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PowerPreference {
+    High,
+    Low,
+}
+
+impl Args {
+    pub const fn cache_budget(&self) -> usize {
+        self.cache_size.saturating_mul(MIB)
+    }
+
+    /// iced creates the wgpu instance and adapter itself, and only reads
+    /// the choice from the environment. The batch device reads it from there
+    /// as well.
+    pub fn select_adapter(&self) {
+        let backend = self.backend.map(|backend| match backend {
+            Backend::Vulkan => "vulkan",
+            Backend::Metal => "metal",
+            Backend::Dx12 => "dx12",
+            Backend::Gl => "gl",
+        });
+        let power_preference = self.power_preference.map(|preference| match preference {
+            PowerPreference::High => "high",
+            PowerPreference::Low => "low",
+        });
+        let adapter = match &self.command {
+            Some(Command::Batch(args)) => args.adapter.as_deref(),
+            _ => None,
+        };
+        let variables = [
+            ("WGPU_BACKEND", backend),
+            ("WGPU_POWER_PREF", power_preference),
+            ("WGPU_ADAPTER_NAME", adapter),
+        ];
+        for (name, value) in variables {
+            if let Some(value) = value {
+                // SAFETY: called from main before any other thread is started
+                unsafe { std::env::set_var(name, value) };
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Row-major 3x3 matrix
+pub type Matrix = [[f64; 3]; 3];
+
+/// The profile connection space of ICC profiles is relative to D50
+pub const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+const D65_XY: (f64, f64) = (0.3127, 0.3290);
+const D50_XY: (f64, f64) = (0.3457, 0.3585);
+
+/// Cone response domain used for chromatic adaptation
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+/// The color space the processed image is encoded in, for display and export
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputColorSpace {
+    #[default]
+    #[display("sRGB")]
+    Srgb,
+    #[display("Display P3")]
+    DisplayP3,
+    #[display("Adobe RGB")]
+    AdobeRgb,
+    #[display("Rec. 2020")]
+    Rec2020,
+    #[display("ProPhoto RGB")]
+    ProPhoto,
+    /// sRGB primaries without a transfer curve
+    #[display("Linear sRGB")]
+    Linear,
+}
+
+/// How linear values are encoded in an output color space
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Linear,
+    /// The piecewise sRGB curve, also used by Display P3
+    #[default]
+    Srgb,
+    /// A pure power of 563/256, as specified for Adobe RGB
+    AdobeRgb,
+    /// The BT.709 camera curve that Rec. 2020 shares
+    Rec709,
+    /// A power of 1.8 with a linear toe, as specified for ROMM RGB
+    ProPhoto,
+}
+
+/// CIE xy chromaticities of the primaries and the white point
+struct Primaries {
+    red: (f64, f64),
+    green: (f64, f64),
+    blue: (f64, f64),
+    white: (f64, f64),
+}
+
+impl OutputColorSpace {
+    pub const ALL: [Self; 6] = [
+        Self::Srgb,
+        Self::DisplayP3,
+        Self::AdobeRgb,
+        Self::Rec2020,
+        Self::ProPhoto,
+        Self::Linear,
+    ];
+
+    pub const fn transfer(self) -> Transfer {
+        match self {
+            Self::Srgb | Self::DisplayP3 => Transfer::Srgb,
+            Self::AdobeRgb => Transfer::AdobeRgb,
+            Self::Rec2020 => Transfer::Rec709,
+            Self::ProPhoto => Transfer::ProPhoto,
+            Self::Linear => Transfer::Linear,
+        }
+    }
+
+    const fn primaries(self) -> Primaries {
+        match self {
+            Self::Srgb | Self::Linear => Primaries {
+                red: (0.64, 0.33),
+                green: (0.30, 0.60),
+                blue: (0.15, 0.06),
+                white: D65_XY,
+            },
+            Self::DisplayP3 => Primaries {
+                red: (0.680, 0.320),
+                green: (0.265, 0.690),
+                blue: (0.150, 0.060),
+                white: D65_XY,
+            },
+            Self::AdobeRgb => Primaries {
+                red: (0.64, 0.33),
+                green: (0.21, 0.71),
+                blue: (0.15, 0.06),
+                white: D65_XY,
+            },
+            Self::Rec2020 => Primaries {
+                red: (0.708, 0.292),
+                green: (0.170, 0.797),
+                blue: (0.131, 0.046),
+                white: D65_XY,
+            },
+            Self::ProPhoto => Primaries {
+                red: (0.7347, 0.2653),
+                green: (0.1596, 0.8404),
+                blue: (0.0366, 0.0001),
+                white: D50_XY,
+            },
+        }
+    }
+
+    /// Linear RGB to XYZ, relative to the white point of the color space
+    pub fn rgb_to_xyz(self) -> Matrix {
+        let primaries = self.primaries();
+        let columns = [primaries.red, primaries.green, primaries.blue].map(xy_to_xyz);
+        let unscaled = transpose(columns);
+        // Scale the primaries so that RGB white has the XYZ of the white point
+        let scale = mul_vector(invert(unscaled), xy_to_xyz(primaries.white));
+        unscaled.map(|row| std::array::from_fn(|i| row[i] * scale[i]))
+    }
+
+    /// The camera matrix maps to XYZ relative to D65, which is adapted to
+    /// the white point of the color space before converting to RGB
+    pub fn xyz_to_rgb(self) -> Matrix {
+        let white = xy_to_xyz(self.primaries().white);
+        multiply(
+            invert(self.rgb_to_xyz()),
+            adaptation(xy_to_xyz(D65_XY), white),
+        )
+    }
+
+    /// Linear RGB to linear sRGB, for showing the output on the sRGB
+    /// surface of the window
+    pub fn rgb_to_srgb(self) -> Matrix {
+        multiply(Self::Srgb.xyz_to_rgb(), invert(self.xyz_to_rgb()))
+    }
+
+    /// Linear RGB to the D50 relative XYZ of the ICC connection space
+    pub fn rgb_to_pcs(self) -> Matrix {
+        let white = xy_to_xyz(self.primaries().white);
+        multiply(adaptation(white, D50), self.rgb_to_xyz())
+    }
+}
+
+impl Transfer {
+    /// The id the processing shader switches on
+    pub const fn to_shader(self) -> u32 {
+        match self {
+            Self::Linear => 0,
+            Self::Srgb => 1,
+            Self::AdobeRgb => 2,
+            Self::Rec709 => 3,
+            Self::ProPhoto => 4,
+        }
+    }
+
+    /// Encoded value to linear light
+    pub fn decode(self, v: f64) -> f64 {
+        match self {
+            Self::Linear => v,
+            Self::Srgb if v <= 0.040_45 => v / 12.92,
+            Self::Srgb => ((v + 0.055) / 1.055).powf(2.4),
+            Self::AdobeRgb => v.powf(563.0 / 256.0),
+            Self::Rec709 if v < 0.081_242_858 => v / 4.5,
+            Self::Rec709 => ((v + 0.099_296_826) / 1.099_296_826).powf(1.0 / 0.45),
+            Self::ProPhoto if v < 16.0 / 512.0 => v / 16.0,
+            Self::ProPhoto => v.powf(1.8),
+        }
+    }
+
+    /// Linear light to the encoded value, like `transfer` in the processing
+    /// shader
+    #[cfg(test)]
+    fn encode(self, v: f64) -> f64 {
+        match self {
+            Self::Linear => v,
+            Self::Srgb if v <= 0.003_130_8 => v * 12.92,
+            Self::Srgb => 1.055_f64.mul_add(v.powf(1.0 / 2.4), -0.055),
+            Self::AdobeRgb => v.powf(256.0 / 563.0),
+            Self::Rec709 if v < 0.018_053_968 => v * 4.5,
+            Self::Rec709 => 1.099_296_826_f64.mul_add(v.powf(0.45), -0.099_296_826),
+            Self::ProPhoto if v < 1.0 / 512.0 => v * 16.0,
+            Self::ProPhoto => v.powf(1.0 / 1.8),
+        }
+    }
+}
+
+/// The columns of the matrix, as `mat3x3` uniforms are laid out
+pub fn to_columns(matrix: Matrix) -> [[f32; 3]; 3] {
+    transpose(matrix).map(|column| column.map(|v| v as f32))
+}
+
+/// Bradford adaptation from one white point to another, both in XYZ
+fn adaptation(from: [f64; 3], to: [f64; 3]) -> Matrix {
+    let from = mul_vector(BRADFORD, from);
+    let to = mul_vector(BRADFORD, to);
+    let scale = [
+        [to[0] / from[0], 0.0, 0.0],
+        [0.0, to[1] / from[1], 0.0],
+        [0.0, 0.0, to[2] / from[2]],
+    ];
+    multiply(invert(BRADFORD), multiply(scale, BRADFORD))
+}
+
+fn xy_to_xyz((x, y): (f64, f64)) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+fn transpose(m: Matrix) -> Matrix {
+    std::array::from_fn(|row| std::array::from_fn(|col| m[col][row]))
+}
+
+fn multiply(a: Matrix, b: Matrix) -> Matrix {
+    std::array::from_fn(|row| {
+        std::array::from_fn(|col| (0..3).map(|i| a[row][i] * b[i][col]).sum())
+    })
+}
+
+fn mul_vector(m: Matrix, v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| (0..3).map(|i| row[i] * v[i]).sum())
+}
+
+/// Inverse by cofactors. Every matrix inverted here is built from well
+/// separated primaries, so none is singular.
+fn invert(m: Matrix) -> Matrix {
+    let cofactor = |row: usize, col: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((col + 1) % 3, (col + 2) % 3);
+        m[r0][c0].mul_add(m[r1][c1], -(m[r0][c1] * m[r1][c0]))
+    };
+    let determinant: f64 = (0..3).map(|col| m[0][col] * cofactor(0, col)).sum();
+    std::array::from_fn(|row| std::array::from_fn(|col| cofactor(col, row) / determinant))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Matrix, b: Matrix, tolerance: f64) {
+        for (row_a, row_b) in a.iter().zip(b) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < tolerance, "{a:?} != {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_srgb_matrix() {
+        let expected = [
+            [3.2406, -1.5372, -0.4986],
+            [-0.9689, 1.8758, 0.0415],
+            [0.0557, -0.2040, 1.0570],
+        ];
+        assert_close(OutputColorSpace::Srgb.xyz_to_rgb(), expected, 1e-3);
+    }
+
+    #[test]
+    fn test_white_maps_to_pcs_white() {
+        for space in OutputColorSpace::ALL {
+            let white = mul_vector(space.rgb_to_pcs(), [1.0; 3]);
+            assert_close([white; 3], [D50; 3], 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_rgb_to_srgb() {
+        let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_close(OutputColorSpace::Srgb.rgb_to_srgb(), identity, 1e-9);
+        for space in OutputColorSpace::ALL {
+            let white = mul_vector(space.rgb_to_srgb(), [1.0; 3]);
+            assert_close([white; 3], [[1.0; 3]; 3], 1e-9);
+        }
+        // Pure P3 green lies outside of sRGB
+        let green = mul_vector(OutputColorSpace::DisplayP3.rgb_to_srgb(), [0.0, 1.0, 0.0]);
+        assert!(green[0] < 0.0 && green[1] > 1.0, "{green:?}");
+    }
+
+    #[test]
+    fn test_transfer_round_trip() {
+        for space in OutputColorSpace::ALL {
+            let transfer = space.transfer();
+            for i in 0..=100 {
+                let v = f64::from(i) / 100.0;
+                let decoded = transfer.decode(transfer.encode(v));
+                assert!((decoded - v).abs() < 1e-6, "{transfer:?} {v} {decoded}");
+            }
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use std::borrow::Cow;
 
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
 use wgpu::PipelineCompilationOptions;
 
 use crate::{
@@ -9,13 +11,94 @@ use crate::{
 
 pub struct DemosaicShader;
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DemosaicAlgorithm {
+    #[default]
+    Bilinear,
+    #[display("Malvar-He-Cutler")]
+    MalvarHeCutler,
+    #[display("PPG")]
+    Ppg,
+    #[display("VNG")]
+    Vng,
+    #[display("AHD")]
+    Ahd,
+}
+
+impl DemosaicAlgorithm {
+    pub const ALL: [Self; 5] = [
+        Self::Bilinear,
+        Self::MalvarHeCutler,
+        Self::Ppg,
+        Self::Vng,
+        Self::Ahd,
+    ];
+
+    const fn source(self) -> &'static str {
+        match self {
+            Self::Bilinear => concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/demosaic.wgsl"),
+                include_str!("../shader/demosaic_bilinear.wgsl")
+            ),
+            Self::MalvarHeCutler => concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/demosaic.wgsl"),
+                include_str!("../shader/demosaic_malvar.wgsl")
+            ),
+            Self::Ppg => concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/demosaic.wgsl"),
+                include_str!("../shader/demosaic_ppg.wgsl")
+            ),
+            Self::Vng => concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/demosaic.wgsl"),
+                include_str!("../shader/demosaic_vng.wgsl")
+            ),
+            Self::Ahd => concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/demosaic.wgsl"),
+                include_str!("../shader/demosaic_ahd.wgsl")
+            ),
+        }
+    }
+}
+
+const XTRANS_SOURCE: &str = concat!(
+    include_str!("../shader/uniforms.wgsl"),
+    include_str!("../shader/demosaic.wgsl"),
+    include_str!("../shader/demosaic_xtrans.wgsl")
+);
+
 impl DemosaicShader {
     pub fn compile(
         device: &wgpu::Device,
         uniforms: &wgpu::Buffer,
         textures: &Textures,
+        algorithm: DemosaicAlgorithm,
+    ) -> ComputeShaderData {
+        Self::compile_source(device, uniforms, textures, algorithm.source())
+    }
+
+    /// The demosaic pass for 6x6 X-Trans sensors, which the Bayer
+    /// algorithms can't handle.
+    pub fn compile_xtrans(
+        device: &wgpu::Device,
+        uniforms: &wgpu::Buffer,
+        textures: &Textures,
     ) -> ComputeShaderData {
-        let pipeline = Self::create_pipeline(device);
+        Self::compile_source(device, uniforms, textures, XTRANS_SOURCE)
+    }
+
+    fn compile_source(
+        device: &wgpu::Device,
+        uniforms: &wgpu::Buffer,
+        textures: &Textures,
+        source: &'static str,
+    ) -> ComputeShaderData {
+        let pipeline = Self::create_pipeline(device, source);
         let (bind_group, uniform_bind_group) =
             Self::create_bind_group(device, &pipeline, uniforms, textures);
         ComputeShaderData {
@@ -26,12 +109,10 @@ impl DemosaicShader {
         }
     }
 
-    pub fn create_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    pub fn create_pipeline(device: &wgpu::Device, source: &'static str) -> wgpu::ComputePipeline {
         let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("demosaic_shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                "../shader/demosaic.wgsl"
-            ))),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
         });
 
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -111,3 +192,203 @@ impl DemosaicShader {
         (bind_group, uniform_bind_group)
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::{
+        compute, primitive,
+        uniforms::{self, Uniforms},
+        util::Tof32,
+    };
+
+    const SIZE: u32 = 12;
+    const COLOR: [f32; 3] = [0.2, 0.5, 0.8];
+    /// The X-Trans layout of Fujifilm's sensors
+    const XTRANS: [[u32; 6]; 6] = [
+        [1, 1, 0, 1, 1, 2],
+        [1, 1, 2, 1, 1, 0],
+        [2, 0, 1, 0, 2, 1],
+        [1, 1, 2, 1, 1, 0],
+        [1, 1, 0, 1, 1, 2],
+        [0, 2, 1, 2, 0, 1],
+    ];
+
+    /// The sensor values of `image` sampled through the CFA pattern.
+    fn mosaic(cfa: [[u32; 6]; 6], image: impl Fn(u32, u32) -> [f32; 3]) -> Vec<f32> {
+        let image = &image;
+        (0..SIZE)
+            .flat_map(|y| {
+                (0..SIZE)
+                    .map(move |x| image(x, y)[cfa[(y % 6) as usize][(x % 6) as usize] as usize])
+            })
+            .collect()
+    }
+
+    fn run_demosaic(
+        cfa: [[u32; 6]; 6],
+        cfa_size: u32,
+        image: impl Fn(u32, u32) -> [f32; 3],
+        compile: impl FnOnce(&wgpu::Device, &wgpu::Buffer, &Textures) -> ComputeShaderData,
+    ) -> Option<Vec<f32>> {
+        let (device, queue) = compute::test_device()?;
+        let size = iced::Size::new(SIZE, SIZE);
+        let textures = compute::test_textures(&device, size);
+        queue.write_texture(
+            textures.full_texture.as_image_copy(),
+            bytemuck::cast_slice(&mosaic(cfa, image)),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * SIZE),
+                rows_per_image: Some(SIZE),
+            },
+            textures.full_texture.size(),
+        );
+
+        let uniforms_buffer = primitive::create_uniforms_buffer(&device);
+        let uniforms = Uniforms {
+            image_size: size.to_f32(),
+            cfa,
+            cfa_size,
+            ..Default::default()
+        };
+        queue.write_buffer(
+            &uniforms_buffer,
+            0,
+            bytemuck::bytes_of(&uniforms.to_raw(size.to_f32())),
+        );
+
+        let shader = compile(&device, &uniforms_buffer, &textures);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        compute::enqueue_workload(&mut encoder, &shader);
+        queue.submit(Some(encoder.finish()));
+        Some(compute::read_texture(&device, &queue, &textures.full_output_texture).unwrap())
+    }
+
+    /// Compares the pixels at least `margin` away from the image edges
+    fn assert_image(output: &[f32], image: impl Fn(u32, u32) -> [f32; 3], margin: u32, name: &str) {
+        let inside = margin..SIZE - margin;
+        for (i, pixel) in output.chunks(4).enumerate() {
+            let (x, y) = (i as u32 % SIZE, i as u32 / SIZE);
+            if !inside.contains(&x) || !inside.contains(&y) {
+                continue;
+            }
+            let expected = image(x, y);
+            for channel in 0..3 {
+                assert!(
+                    (pixel[channel] - expected[channel]).abs() < 1e-5,
+                    "{name}: pixel {i} channel {channel} is {}, expected {}",
+                    pixel[channel],
+                    expected[channel]
+                );
+            }
+        }
+    }
+
+    /// Runs every Bayer algorithm on `image`, sampled through the pattern
+    fn check_bayer(
+        pattern: [u32; 4],
+        image: impl Fn(u32, u32) -> [f32; 3],
+        margin: u32,
+        name: &str,
+    ) {
+        for algorithm in DemosaicAlgorithm::ALL {
+            // Skip silently on machines without any wgpu adapter
+            let Some(output) = run_demosaic(
+                uniforms::tile_cfa(pattern),
+                2,
+                &image,
+                |device, uniforms, textures| {
+                    DemosaicShader::compile(device, uniforms, textures, algorithm)
+                },
+            ) else {
+                return;
+            };
+            assert_image(
+                &output,
+                &image,
+                margin,
+                &format!("{algorithm} {pattern:?} {name}"),
+            );
+        }
+    }
+
+    fn check_pattern(pattern: [u32; 4]) {
+        // A flat field must come out flat with every algorithm
+        check_bayer(pattern, |_, _| COLOR, 0, "flat");
+    }
+
+    /// Runs the X-Trans pass on `image`, for every phase of the pattern
+    fn check_xtrans(image: impl Fn(u32, u32) -> [f32; 3], name: &str) {
+        for shift in 0..6 {
+            let mut cfa = XTRANS;
+            cfa.rotate_left(shift);
+            for row in &mut cfa {
+                row.rotate_left(shift);
+            }
+            let Some(output) = run_demosaic(cfa, 6, &image, DemosaicShader::compile_xtrans) else {
+                return;
+            };
+            assert_image(
+                &output,
+                &image,
+                0,
+                &format!("X-Trans {name}, shifted by {shift}"),
+            );
+        }
+    }
+
+    #[test]
+    fn test_demosaic_rggb() {
+        check_pattern([0, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_demosaic_grbg() {
+        check_pattern([1, 0, 2, 1]);
+    }
+
+    #[test]
+    fn test_demosaic_gbrg() {
+        check_pattern([1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn test_demosaic_bggr() {
+        check_pattern([2, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_demosaic_bayer_ramp() {
+        // With the same slope in every channel, the color differences are
+        // constant and the interpolation along any direction is exact. The
+        // mirrored edges bend the ramp, so the three pixels that PPG and AHD
+        // reach into are left out.
+        let ramp = |x: u32, y: u32| {
+            let level = (x + 2 * y) as f32 / 64.0;
+            [0.125 + level, 0.25 + level, 0.375 + level]
+        };
+        for pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+            check_bayer(pattern, ramp, 3, "ramp");
+        }
+    }
+
+    #[test]
+    fn test_demosaic_xtrans_flat() {
+        check_xtrans(|_, _| COLOR, "flat");
+    }
+
+    #[test]
+    fn test_demosaic_xtrans_edges() {
+        // Both sides have the same color differences, so the direction
+        // along the edge reconstructs it exactly, while blending in the
+        // directions across it would not. The values are exact in binary,
+        // so the derivatives along the edge are exactly zero.
+        let dark = [0.25, 0.5, 0.75];
+        let light = [0.5, 0.75, 1.0];
+        check_xtrans(|x, _| if x < 5 { dark } else { light }, "vertical edge");
+        check_xtrans(|_, y| if y < 7 { dark } else { light }, "horizontal edge");
+    }
+}
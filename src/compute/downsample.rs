@@ -29,8 +29,9 @@ impl DownsampleShader {
     pub fn create_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
         let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("downsample_shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                "../shader/downsample.wgsl"
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/downsample.wgsl")
             ))),
         });
 
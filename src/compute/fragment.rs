@@ -38,9 +38,10 @@ impl FragmentShader {
 
         let module = &device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("fragment.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(include_str!(
-                "../shader/fragment.wgsl"
-            ),))),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/fragment.wgsl")
+            ))),
         });
 
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
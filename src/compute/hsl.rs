@@ -0,0 +1,118 @@
+use std::borrow::Cow;
+
+use wgpu::PipelineCompilationOptions;
+
+use crate::{
+    compute::{to_texture_view, uniforms_bind_group, uniforms_bind_group_layout},
+    renderer::{ComputeShaderData, Textures},
+};
+
+/// The hue, saturation and luminance adjustments, between the processing
+/// pass and its `encode` pass
+pub struct HslShader;
+
+impl HslShader {
+    pub fn compile(
+        device: &wgpu::Device,
+        uniforms: &wgpu::Buffer,
+        textures: &Textures,
+    ) -> ComputeShaderData {
+        let pipeline = Self::create_pipeline(device);
+        let (bind_group, uniform_bind_group) =
+            Self::create_bind_group(device, &pipeline, uniforms, textures);
+        ComputeShaderData {
+            pipeline,
+            bind_group,
+            uniform_bind_group,
+            size: textures.output_size,
+        }
+    }
+
+    pub fn create_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+        let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("hsl_shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/hsl.wgsl")
+            ))),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("hsl_pipeline_layout"),
+            bind_group_layouts: &[
+                &Self::create_bind_group_layout(device),
+                &uniforms_bind_group_layout(device),
+            ],
+            push_constant_ranges: &[],
+        });
+
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("hsl_pipeline"),
+            layout: Some(&layout),
+            module: &cs_module,
+            entry_point: Some("main"),
+            compilation_options: PipelineCompilationOptions::default(),
+            cache: None,
+        })
+    }
+
+    fn create_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("hsl_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn create_bind_group(
+        device: &wgpu::Device,
+        pipeline: &wgpu::ComputePipeline,
+        uniforms: &wgpu::Buffer,
+        textures: &Textures,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+        // The processing pass wrote its XYZ to the output texture and has
+        // no use for its input anymore, so the two swap roles here
+        let xyz_view = to_texture_view(&textures.output_texture);
+        let adjusted_view = to_texture_view(&textures.input_texture);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("hsl_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&xyz_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&adjusted_view),
+                },
+            ],
+        });
+
+        let uniform_bind_group_layout = pipeline.get_bind_group_layout(1);
+        let uniform_bind_group = uniforms_bind_group(device, &uniform_bind_group_layout, uniforms);
+        (bind_group, uniform_bind_group)
+    }
+}
@@ -1,10 +1,13 @@
+use std::borrow::Cow;
+
 use image::GenericImageView;
 
-use crate::{program, renderer::ComputeShaderData, util::Resize};
+use crate::{program, renderer::ComputeShaderData};
 
 pub mod demosaic;
 pub mod downsample;
 pub mod fragment;
+pub mod hsl;
 pub mod processing;
 
 pub fn enqueue_workload(encoder: &mut wgpu::CommandEncoder, shader: &ComputeShaderData) {
@@ -93,35 +96,12 @@ pub fn create_float_texture(
         format,
         usage: wgpu::TextureUsages::STORAGE_BINDING
             | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC
             | wgpu::TextureUsages::TEXTURE_BINDING,
         view_formats: &[format],
     })
 }
 
-pub fn create_window_texture(
-    device: &wgpu::Device,
-    window_size: iced::Size<u32>,
-    image_size: iced::Size<u32>,
-) -> wgpu::Texture {
-    let size = crate::util::calculate_image_size(window_size, image_size).resize(1.2);
-    device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("Storage Texture"),
-        size: wgpu::Extent3d {
-            width: size.width,
-            height: size.height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba32Float,
-        usage: wgpu::TextureUsages::STORAGE_BINDING
-            | wgpu::TextureUsages::COPY_DST
-            | wgpu::TextureUsages::TEXTURE_BINDING,
-        view_formats: &[wgpu::TextureFormat::Rgba32Float],
-    })
-}
-
 pub fn to_texture_view(texture: &wgpu::Texture) -> wgpu::TextureView {
     texture.create_view(&wgpu::TextureViewDescriptor {
         label: Some("compute_image_texture_view"),
@@ -137,36 +117,21 @@ pub fn to_texture_view(texture: &wgpu::Texture) -> wgpu::TextureView {
     })
 }
 
-pub fn write_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, image: &program::Image) {
+pub fn write_texture(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    image: &program::Image,
+) -> crate::Result<()> {
     let (width, height) = image.dimensions();
-    let data: Vec<u8> = match image {
-        program::Image::DynamicImage(img) => bytemuck::cast_slice(
-            &img.to_rgba8()
-                .iter()
-                .map(|v| f32::from(*v) / 255.0)
-                .collect::<Vec<f32>>(),
-        )
-        .to_vec(),
-        program::Image::RawImage(raw) => match &raw.data {
-            rawloader::RawImageData::Integer(items) => {
-                bytemuck::cast_slice(&items.iter().copied().map(f32::from).collect::<Vec<f32>>())
-                    .to_vec()
-            }
-            #[allow(clippy::panic)]
-            rawloader::RawImageData::Float(_) => panic!("Not supported"),
-        },
-    };
+    let data = texture_data(image)?;
 
     let bytes_per_pixel = match image {
-        program::Image::DynamicImage(_) => 16, // RGBA32Float
-        program::Image::RawImage(_) => 4,      // R32Float
+        program::Image::DynamicImage(_)
+        | program::Image::LinearImage(_)
+        | program::Image::Preview(_) => 16, // RGBA32Float
+        program::Image::RawImage(_) => 4, // R32Float
     };
 
-    // info!("Writing texture of size {}x{}", width, height);
-    // info!("Data length: {}", data.len());
-    // info!("Bytes per row: {}", 4 * width);
-    // info!("Rows per image: {}", data.len() as u32 / (4 * width));
-    // info!("Texture: {:?}", texture.format());
     queue.write_texture(
         wgpu::TexelCopyTextureInfo {
             texture,
@@ -174,7 +139,7 @@ pub fn write_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, image: &progr
             origin: wgpu::Origin3d::ZERO,
             aspect: wgpu::TextureAspect::All,
         },
-        &data,
+        bytemuck::cast_slice::<f32, u8>(&data),
         wgpu::TexelCopyBufferLayout {
             offset: 0,
             bytes_per_row: Some(bytes_per_pixel * width),
@@ -186,6 +151,115 @@ pub fn write_texture(queue: &wgpu::Queue, texture: &wgpu::Texture, image: &progr
             depth_or_array_layers: 1,
         },
     );
+    Ok(())
+}
+
+/// Converts the image into the texels of its full size texture. Images that
+/// went through `Image::prepare` are borrowed without any conversion.
+pub fn texture_data(image: &program::Image) -> crate::Result<Cow<'_, [f32]>> {
+    match image {
+        program::Image::DynamicImage(image::DynamicImage::ImageRgba32F(img))
+        | program::Image::LinearImage(image::DynamicImage::ImageRgba32F(img))
+        | program::Image::Preview(image::DynamicImage::ImageRgba32F(img)) => {
+            Ok(Cow::Borrowed(img.as_raw()))
+        }
+        // Keeps the full precision of 16 bit and float sources
+        program::Image::DynamicImage(img)
+        | program::Image::LinearImage(img)
+        | program::Image::Preview(img) => Ok(Cow::Owned(img.to_rgba32f().into_raw())),
+        program::Image::RawImage(raw) => raw_texture_data(raw),
+    }
+}
+
+pub fn raw_texture_data(raw: &rawloader::RawImage) -> crate::Result<Cow<'_, [f32]>> {
+    check_raw_data(raw)?;
+    Ok(match &raw.data {
+        rawloader::RawImageData::Integer(items) => {
+            Cow::Owned(items.iter().copied().map(f32::from).collect())
+        }
+        // Already in sensor units, see `float_raw_to_sensor_units`
+        rawloader::RawImageData::Float(items) if items.iter().copied().all(is_valid) => {
+            Cow::Borrowed(items)
+        }
+        rawloader::RawImageData::Float(items) => {
+            Cow::Owned(items.iter().copied().map(sanitize).collect())
+        }
+    })
+}
+
+/// Checks that the raw has one sample for every pixel
+pub fn check_raw_data(raw: &rawloader::RawImage) -> crate::Result<()> {
+    if raw.cpp != 1 {
+        return Err(format!("Unsupported raw with {} components per pixel", raw.cpp).into());
+    }
+    let expected = raw.width * raw.height;
+    let len = match &raw.data {
+        rawloader::RawImageData::Integer(items) => items.len(),
+        rawloader::RawImageData::Float(items) => items.len(),
+    };
+    if len != expected {
+        return Err(format!("Raw data has {len} samples, expected {expected}").into());
+    }
+    Ok(())
+}
+
+/// How the samples of a floating point raw are scaled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatScale {
+    /// In the same units as the white and black levels
+    SensorUnits,
+    /// From 0 to 1, while rawloader still reports integer levels
+    Normalized,
+}
+
+impl FloatScale {
+    /// The DNG spec puts float samples in the units of its `WhiteLevel` tag,
+    /// which defaults to 1.0. Without the tag the levels rawloader reports
+    /// are its own integer defaults.
+    pub const fn from_white_level(white_level: Option<u32>) -> Self {
+        match white_level {
+            Some(_) => Self::SensorUnits,
+            None => Self::Normalized,
+        }
+    }
+}
+
+/// Maps floating point samples into the same sensor units as integer raws,
+/// so the processing shader can apply the white and black levels unchanged.
+/// Normalized samples are stretched between the black and white level of
+/// their CFA color.
+pub fn float_raw_to_sensor_units(raw: &mut rawloader::RawImage, scale: FloatScale) {
+    if let rawloader::RawImageData::Float(items) = &mut raw.data {
+        let levels = (raw.whitelevels, raw.blacklevels);
+        scale_samples(items, scale, raw.width, &raw.cfa, levels);
+    }
+}
+
+fn scale_samples(
+    items: &mut [f32],
+    scale: FloatScale,
+    width: usize,
+    cfa: &rawloader::CFA,
+    (whitelevels, blacklevels): ([u16; 4], [u16; 4]),
+) {
+    if scale == FloatScale::SensorUnits {
+        return;
+    }
+    let width = width.max(1);
+    for (i, v) in items.iter_mut().enumerate() {
+        let color = cfa.color_at(i / width, i % width);
+        let white = f32::from(whitelevels[color]);
+        let black = f32::from(blacklevels[color]);
+        *v = sanitize(*v).mul_add(white - black, black);
+    }
+}
+
+const fn is_valid(v: f32) -> bool {
+    v.is_finite() && v >= 0.0
+}
+
+const fn sanitize(v: f32) -> f32 {
+    if is_valid(v) { v } else { 0.0 }
 }
 
 pub fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
@@ -200,3 +274,213 @@ pub fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
         ..Default::default()
     })
 }
+
+#[cfg(test)]
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter =
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .ok()?;
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).ok()
+}
+
+/// Textures of `size` for running single passes in tests, with raw sensor
+/// values in the full size texture
+#[cfg(test)]
+pub fn test_textures(device: &wgpu::Device, size: iced::Size<u32>) -> crate::renderer::Textures {
+    let rgba = || create_float_texture(device, size, wgpu::TextureFormat::Rgba32Float);
+    crate::renderer::Textures {
+        full_texture: create_float_texture(device, size, wgpu::TextureFormat::R32Float),
+        full_output_texture: rgba(),
+        input_texture: rgba(),
+        output_texture: rgba(),
+        curve_lut: processing::create_curve_texture(device),
+        lut: processing::create_lut_texture(device, 1),
+        image_size: size,
+        output_size: size,
+    }
+}
+
+/// Largest readback buffer, well below the default `max_buffer_size` of
+/// 256 MiB, which a full resolution RGBA32F image easily exceeds
+const MAX_READBACK_BYTES: u64 = 64 << 20;
+
+/// Copies a float texture back to the CPU, one `f32` per channel.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> crate::Result<Vec<f32>> {
+    let max_bytes = MAX_READBACK_BYTES.min(device.limits().max_buffer_size);
+    read_texture_in_bands(device, queue, texture, max_bytes)
+}
+
+/// Reads the texture a band of rows at a time, through one buffer of at most
+/// `max_bytes`
+fn read_texture_in_bands(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    max_bytes: u64,
+) -> crate::Result<Vec<f32>> {
+    let bytes_per_pixel = texture
+        .format()
+        .block_copy_size(None)
+        .ok_or("Texture format can't be copied")?;
+    let unpadded_bytes_per_row = bytes_per_pixel * texture.width();
+    let padded_bytes_per_row =
+        unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let band_height = u32::try_from(max_bytes / u64::from(padded_bytes_per_row))
+        .unwrap_or(u32::MAX)
+        .clamp(1, texture.height());
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback_buffer"),
+        size: u64::from(padded_bytes_per_row) * u64::from(band_height),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut data =
+        Vec::with_capacity((unpadded_bytes_per_row / 4) as usize * texture.height() as usize);
+    for y in (0..texture.height()).step_by(band_height as usize) {
+        let rows = band_height.min(texture.height() - y);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback_encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                origin: wgpu::Origin3d { x: 0, y, z: 0 },
+                ..texture.as_image_copy()
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(rows),
+                },
+            },
+            wgpu::Extent3d {
+                height: rows,
+                ..texture.size()
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..u64::from(padded_bytes_per_row) * u64::from(rows));
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        for row in slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+        {
+            data.extend(bytemuck::pod_collect_to_vec::<u8, f32>(
+                &row[..unpadded_bytes_per_row as usize],
+            ));
+        }
+        buffer.unmap();
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn round_trip(image: &image::DynamicImage, format: image::ImageFormat) -> Vec<f32> {
+        let mut encoded = Cursor::new(Vec::new());
+        image.write_to(&mut encoded, format).unwrap();
+        let decoded =
+            program::Image::DynamicImage(image::load_from_memory(encoded.get_ref()).unwrap());
+        texture_data(&decoded).unwrap().into_owned()
+    }
+
+    #[test]
+    fn test_16_bit_gradient_round_trip() {
+        // Neighboring values collapse to the same level at 8 bits
+        let gradient = image::DynamicImage::from(image::ImageBuffer::from_fn(256, 1, |x, _| {
+            let v = 30_000 + x as u16;
+            image::Rgba([v, v, v, u16::MAX])
+        }));
+        for format in [image::ImageFormat::Png, image::ImageFormat::Tiff] {
+            let data = round_trip(&gradient, format);
+            for (x, pixel) in data.chunks(4).enumerate() {
+                let expected = f32::from(30_000 + x as u16) / f32::from(u16::MAX);
+                assert!((pixel[0] - expected).abs() < 1e-7, "{format:?} pixel {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_float_gradient_round_trip() {
+        let gradient = image::DynamicImage::from(image::ImageBuffer::from_fn(256, 1, |x, _| {
+            let v = x as f32 / 64.0;
+            image::Rgba([v, v, v, 1.0])
+        }));
+        let data = round_trip(&gradient, image::ImageFormat::OpenExr);
+        for (x, pixel) in data.chunks(4).enumerate() {
+            assert!((pixel[0] - x as f32 / 64.0).abs() < 1e-6, "pixel {x}");
+        }
+    }
+
+    fn assert_close(samples: &[f32], expected: &[f32]) {
+        assert!(
+            samples
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-3),
+            "{samples:?} {expected:?}"
+        );
+    }
+
+    const LEVELS: ([u16; 4], [u16; 4]) = ([1000, 2000, 4000, 4000], [100, 200, 400, 400]);
+
+    #[test]
+    fn test_normalized_float_raw() {
+        let mut samples = [0.0, 0.5, 1.0, 0.25];
+        let scale = FloatScale::from_white_level(None);
+        let cfa = rawloader::CFA::new("RGGB");
+        scale_samples(&mut samples, scale, 2, &cfa, LEVELS);
+        assert_close(&samples, &[100.0, 1100.0, 2000.0, 1300.0]);
+    }
+
+    #[test]
+    fn test_float_raw_in_sensor_units() {
+        // Even a dark image that never exceeds 1 stays in sensor units
+        for original in [[0.0, 0.5, 1.0, 0.25], [100.0, 1100.0, 2000.0, 1300.0]] {
+            let mut samples = original;
+            let scale = FloatScale::from_white_level(Some(65_535));
+            let cfa = rawloader::CFA::new("RGGB");
+            scale_samples(&mut samples, scale, 2, &cfa, LEVELS);
+            assert_close(&samples, &original);
+        }
+    }
+
+    #[test]
+    fn test_read_texture_in_bands() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+        // 80 bytes per row, padded to 256, so 600 bytes fit two rows and the
+        // last band has one
+        let size = iced::Size::new(5, 7);
+        let texture = create_float_texture(&device, size, wgpu::TextureFormat::Rgba32Float);
+        let data: Vec<f32> = (0..5 * 7 * 4).map(|i| i as f32).collect();
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&data),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(5 * 16),
+                rows_per_image: Some(7),
+            },
+            texture.size(),
+        );
+        let read = read_texture_in_bands(&device, &queue, &texture, 600).unwrap();
+        assert!(read.iter().zip(&data).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert_eq!(read.len(), data.len());
+    }
+}
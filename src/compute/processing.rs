@@ -1,21 +1,135 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, ops::RangeInclusive};
 
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
 use wgpu::PipelineCompilationOptions;
 
 use crate::{
     compute::{to_texture_view, uniforms_bind_group, uniforms_bind_group_layout},
+    cube,
+    curve::{self, Curves},
     renderer::{ComputeShaderData, Textures},
 };
 
 pub struct ProcessingShader;
 
+/// How pixels are treated where a sensor channel reached its white level.
+/// The channels clip at different levels once white balanced, which turns
+/// blown highlights magenta or cyan if left alone.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HighlightRecovery {
+    /// Every channel is cut at the lowest clip level, which renders clipped
+    /// areas as neutral white
+    #[default]
+    Clip,
+    /// Desaturates towards the clipped result as pixels approach clipping
+    Blend,
+    /// Estimates clipped channels from the unclipped ones of the same pixel
+    Reconstruct,
+}
+
+impl HighlightRecovery {
+    pub const ALL: [Self; 3] = [Self::Clip, Self::Blend, Self::Reconstruct];
+
+    /// The `HIGHLIGHT_` constant of the processing shader
+    pub const fn to_shader(self) -> u32 {
+        match self {
+            Self::Clip => 0,
+            Self::Blend => 1,
+            Self::Reconstruct => 2,
+        }
+    }
+}
+
+/// How scene-linear values above display white are compressed before they
+/// are encoded for the output color space
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMapping {
+    /// Values above 1 clip
+    #[default]
+    None,
+    /// Compresses the luminance, keeping the hue and saturation
+    Reinhard,
+    /// Hable's curve, between the black and white points of the settings
+    Filmic,
+    /// Hill's fit of the ACES reference rendering and sRGB output transforms
+    #[display("ACES")]
+    Aces,
+}
+
+impl ToneMapping {
+    pub const ALL: [Self; 4] = [Self::None, Self::Reinhard, Self::Filmic, Self::Aces];
+
+    /// The `TONE_` constant of the processing shader
+    pub const fn to_shader(self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Reinhard => 1,
+            Self::Filmic => 2,
+            Self::Aces => 3,
+        }
+    }
+}
+
+/// How colors between the entries of a 3D LUT are looked up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LutInterpolation {
+    /// Blends the eight surrounding entries
+    Trilinear,
+    /// Blends the four entries of the surrounding tetrahedron, which keeps
+    /// neutral colors neutral
+    #[default]
+    Tetrahedral,
+}
+
+impl LutInterpolation {
+    pub const ALL: [Self; 2] = [Self::Trilinear, Self::Tetrahedral];
+
+    /// The `LUT_` constant of the processing shader
+    pub const fn to_shader(self) -> u32 {
+        match self {
+            Self::Trilinear => 0,
+            Self::Tetrahedral => 1,
+        }
+    }
+}
+
+/// Scene-linear value that the filmic curve maps to white
+pub const FILMIC_WHITE_RANGE: RangeInclusive<f32> = 1.0..=16.0;
+/// Scene-linear value that the filmic curve maps to black
+pub const FILMIC_BLACK_RANGE: RangeInclusive<f32> = 0.0..=0.1;
+
 impl ProcessingShader {
+    /// The pass from the downsampled camera values to scene-linear XYZ,
+    /// which the HSL pass picks up
     pub fn compile(
         device: &wgpu::Device,
         uniforms: &wgpu::Buffer,
         textures: &Textures,
     ) -> ComputeShaderData {
-        let pipeline = Self::create_pipeline(device);
+        Self::compile_entry_point(device, uniforms, textures, "main")
+    }
+
+    /// The pass from the adjusted XYZ of the HSL pass to output values,
+    /// through tone mapping, the LUT and the curves
+    pub fn compile_encode(
+        device: &wgpu::Device,
+        uniforms: &wgpu::Buffer,
+        textures: &Textures,
+    ) -> ComputeShaderData {
+        Self::compile_entry_point(device, uniforms, textures, "encode")
+    }
+
+    fn compile_entry_point(
+        device: &wgpu::Device,
+        uniforms: &wgpu::Buffer,
+        textures: &Textures,
+        entry_point: &'static str,
+    ) -> ComputeShaderData {
+        let pipeline = Self::create_pipeline(device, entry_point);
         let (bind_group, uniform_bind_group) =
             Self::create_bind_group(device, &pipeline, uniforms, textures);
         ComputeShaderData {
@@ -25,11 +139,12 @@ impl ProcessingShader {
             size: textures.output_size,
         }
     }
-    pub fn create_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline {
+    pub fn create_pipeline(device: &wgpu::Device, entry_point: &str) -> wgpu::ComputePipeline {
         let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("processing_shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
-                "../shader/processing.wgsl"
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(concat!(
+                include_str!("../shader/uniforms.wgsl"),
+                include_str!("../shader/processing.wgsl")
             ))),
         });
 
@@ -47,7 +162,7 @@ impl ProcessingShader {
             label: Some("processing_pipeline"),
             layout: Some(&layout),
             module: &cs_module,
-            entry_point: Some("main"),
+            entry_point: Some(entry_point),
             compilation_options: PipelineCompilationOptions::default(),
             cache: None,
         })
@@ -87,6 +202,26 @@ impl ProcessingShader {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         })
     }
@@ -100,6 +235,12 @@ impl ProcessingShader {
         let bind_group_layout = compute_pipeline.get_bind_group_layout(0);
         let input_texture_view = to_texture_view(&textures.input_texture);
         let output_texture_view = to_texture_view(&textures.output_texture);
+        let curve_lut_view = textures
+            .curve_lut
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let lut_view = textures
+            .lut
+            .create_view(&wgpu::TextureViewDescriptor::default());
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("compute_bind_group"),
@@ -117,6 +258,14 @@ impl ProcessingShader {
                     binding: 2,
                     resource: wgpu::BindingResource::Buffer(uniforms.as_entire_buffer_binding()),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&curve_lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&lut_view),
+                },
             ],
         });
         let uniform_bind_group_layout = compute_pipeline.get_bind_group_layout(1);
@@ -124,3 +273,319 @@ impl ProcessingShader {
         (bind_group, uniform_bind_group)
     }
 }
+
+/// The texture the processing shader looks the tone curves up in. It is a
+/// single row rather than a 1D texture, which the GL backend can't sample.
+pub fn create_curve_texture(device: &wgpu::Device) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("curve_lut_texture"),
+        size: wgpu::Extent3d {
+            width: curve::LUT_SIZE as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// Evaluates the curves into the LUT texture
+pub fn write_curves(queue: &wgpu::Queue, texture: &wgpu::Texture, curves: &Curves) {
+    queue.write_texture(
+        texture.as_image_copy(),
+        bytemuck::cast_slice(&curves.lut()),
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(size_of::<[f32; 4]>() as u32 * curve::LUT_SIZE as u32),
+            rows_per_image: None,
+        },
+        texture.size(),
+    );
+}
+
+/// A 3D texture with `size` entries along each axis, for a LUT
+pub fn create_lut_texture(device: &wgpu::Device, size: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("lut_texture"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// Uploads the table of the LUT, with red along x, green along y and blue
+/// along z
+pub fn write_lut(queue: &wgpu::Queue, texture: &wgpu::Texture, lut: &cube::Lut) {
+    let texels: Vec<[f32; 4]> = lut.table.iter().map(|&[r, g, b]| [r, g, b, 1.0]).collect();
+    let size = lut.size as u32;
+    queue.write_texture(
+        texture.as_image_copy(),
+        bytemuck::cast_slice(&texels),
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(size_of::<[f32; 4]>() as u32 * size),
+            rows_per_image: Some(size),
+        },
+        texture.size(),
+    );
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Transfer,
+        compute, primitive,
+        uniforms::{InputKind, Uniforms},
+        util::Tof32,
+    };
+
+    const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    const CLIP: [f32; 3] = [2.0, 1.0, 1.5];
+    /// Clip masks as the demosaic pass leaves them in alpha
+    const NONE: u32 = 0;
+    const RED: u32 = 1;
+
+    /// A grey ramp from black to far above white
+    fn ramp() -> Vec<f32> {
+        (0..=400).map(|i| i as f32 / 10.0).collect()
+    }
+
+    /// Runs one pass of the processing shader over a row of pixels
+    fn run_pass(
+        pixels: &[[f32; 4]],
+        uniforms: &Uniforms,
+        compile: impl FnOnce(&wgpu::Device, &wgpu::Buffer, &Textures) -> ComputeShaderData,
+    ) -> Option<Vec<[f32; 4]>> {
+        let (device, queue) = compute::test_device()?;
+        let size = iced::Size::new(pixels.len() as u32, 1);
+        let textures = compute::test_textures(&device, size);
+        queue.write_texture(
+            textures.input_texture.as_image_copy(),
+            bytemuck::cast_slice(pixels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size_of::<[f32; 4]>() as u32 * size.width),
+                rows_per_image: Some(1),
+            },
+            textures.input_texture.size(),
+        );
+        write_curves(&queue, &textures.curve_lut, &Curves::default());
+        let uniforms_buffer = primitive::create_uniforms_buffer(&device);
+        queue.write_buffer(
+            &uniforms_buffer,
+            0,
+            bytemuck::bytes_of(&uniforms.to_raw(size.to_f32())),
+        );
+
+        let shader = compile(&device, &uniforms_buffer, &textures);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        compute::enqueue_workload(&mut encoder, &shader);
+        queue.submit(Some(encoder.finish()));
+        let output = compute::read_texture(&device, &queue, &textures.output_texture).unwrap();
+        Some(bytemuck::pod_collect_to_vec(&output))
+    }
+
+    /// Encodes grey XYZ values without a color space change or transfer
+    /// curve, returning the green channel
+    fn map_grey(
+        tone_mapping: ToneMapping,
+        values: &[f32],
+        white: f32,
+        black: f32,
+    ) -> Option<Vec<f32>> {
+        let pixels: Vec<_> = values.iter().map(|&v| [v, v, v, 1.0]).collect();
+        let uniforms = Uniforms {
+            xyz_2_output: IDENTITY,
+            transfer: Transfer::Linear,
+            tone_mapping,
+            filmic_white: white,
+            filmic_black: black,
+            ..Default::default()
+        };
+        let output = run_pass(&pixels, &uniforms, ProcessingShader::compile_encode)?;
+        Some(output.iter().map(|pixel| pixel[1]).collect())
+    }
+
+    #[test]
+    fn test_tone_mapping_is_monotonic() {
+        for tone_mapping in ToneMapping::ALL {
+            for (white, black) in [(11.2, 0.0), (1.0, 0.0), (16.0, 0.1)] {
+                // Skip silently on machines without any wgpu adapter
+                let Some(mapped) = map_grey(tone_mapping, &ramp(), white, black) else {
+                    return;
+                };
+                assert!(
+                    mapped.windows(2).all(|pair| pair[0] <= pair[1]),
+                    "{tone_mapping} {white} {black}: {mapped:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_filmic_maps_white_to_one() {
+        for (white, black) in [(11.2, 0.0), (1.0, 0.0), (4.0, 0.05), (16.0, 0.1)] {
+            let Some(mapped) = map_grey(ToneMapping::Filmic, &[black, white], white, black) else {
+                return;
+            };
+            assert!(mapped[0].abs() < 1e-5, "{white} {black}: {mapped:?}");
+            assert!(
+                (mapped[1] - 1.0).abs() < 1e-5,
+                "{white} {black}: {mapped:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_aces_maps_white_to_one() {
+        let Some(mapped) = map_grey(ToneMapping::Aces, &[30.0, 0.0, 0.18], 0.0, 0.0) else {
+            return;
+        };
+        // Hill's fit levels off a little above 1 for bright input, which the
+        // clamp turns into white
+        assert!((mapped[0] - 1.0).abs() < 1e-5, "{mapped:?}");
+        assert!(mapped[1].abs() < 1e-3, "{mapped:?}");
+        // Mid grey stays in the lower half
+        assert!(mapped[2] > 0.1 && mapped[2] < 0.5, "{mapped:?}");
+    }
+
+    #[test]
+    fn test_reinhard_approaches_one() {
+        let Some(mapped) = map_grey(ToneMapping::Reinhard, &[1.0, 1000.0], 0.0, 0.0) else {
+            return;
+        };
+        assert!((mapped[0] - 0.5).abs() < 1e-5, "{mapped:?}");
+        assert!(mapped[1] < 1.0 && mapped[1] > 0.99, "{mapped:?}");
+    }
+
+    /// Recovers the highlights of white balanced camera values, each with
+    /// its clip mask, through the processing pass
+    fn recover(
+        highlight_recovery: HighlightRecovery,
+        clip: [f32; 3],
+        pixels: &[([f32; 3], u32)],
+    ) -> Option<Vec<[f32; 3]>> {
+        let pixels: Vec<_> = pixels
+            .iter()
+            .map(|&([r, g, b], mask)| [r, g, b, mask as f32])
+            .collect();
+        let uniforms = Uniforms {
+            input_kind: InputKind::Raw,
+            cam_2_xyz: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+            whitelevels: [clip[0], clip[1], clip[2], 1.0],
+            wb_multipliers: [1.0; 4],
+            highlight_recovery,
+            ..Default::default()
+        };
+        let output = run_pass(&pixels, &uniforms, ProcessingShader::compile)?;
+        Some(output.iter().map(|&[r, g, b, _]| [r, g, b]).collect())
+    }
+
+    /// Checks every pixel that `recover` returns against its expected value
+    fn check_recovery(
+        highlight_recovery: HighlightRecovery,
+        clip: [f32; 3],
+        pixels: &[([f32; 3], u32, [f32; 3])],
+    ) {
+        let input: Vec<_> = pixels.iter().map(|&(v, mask, _)| (v, mask)).collect();
+        // Skip silently on machines without any wgpu adapter
+        let Some(output) = recover(highlight_recovery, clip, &input) else {
+            return;
+        };
+        for (&(v, mask, expected), recovered) in pixels.iter().zip(output) {
+            assert!(
+                recovered
+                    .iter()
+                    .zip(expected)
+                    .all(|(a, b)| (a - b).abs() < 1e-5),
+                "{highlight_recovery} {v:?} with mask {mask}: {recovered:?}, expected {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_clip_cuts_at_the_lowest_level() {
+        // The mask makes no difference, as every channel is cut anyway
+        check_recovery(
+            HighlightRecovery::Clip,
+            CLIP,
+            &[
+                ([1.8, 1.0, 0.5], NONE, [1.0, 1.0, 0.5]),
+                ([1.8, 1.0, 0.5], RED, [1.0, 1.0, 0.5]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_blend_leaves_colors_far_below_the_clip_level() {
+        // Green of the second is 90% of the way to its clip level, so the
+        // blend is halfway to the clipped color
+        check_recovery(
+            HighlightRecovery::Blend,
+            CLIP,
+            &[
+                ([0.2, 0.3, 0.4], NONE, [0.2, 0.3, 0.4]),
+                ([1.6, 0.9, 0.5], NONE, [1.3, 0.9, 0.5]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_blend_follows_the_clip_mask() {
+        check_recovery(
+            HighlightRecovery::Blend,
+            CLIP,
+            &[([1.6, 0.5, 0.5], RED, [1.0, 0.5, 0.5])],
+        );
+    }
+
+    #[test]
+    fn test_reconstruct_from_the_unclipped_channels() {
+        check_recovery(
+            HighlightRecovery::Reconstruct,
+            CLIP,
+            &[
+                ([0.4, 0.5, 0.6], NONE, [0.4, 0.5, 0.6]),
+                // Green is at its clip level, so it is raised to the mean of
+                // the others
+                ([1.8, 1.0, 1.4], NONE, [1.8, 1.6, 1.4]),
+                // Every channel clipped
+                ([2.0, 1.0, 1.5], NONE, [2.0; 3]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_reconstruct_follows_the_clip_mask() {
+        // Red was interpolated from clipped sensor values, which pulled it
+        // below the clip level
+        check_recovery(
+            HighlightRecovery::Reconstruct,
+            [1.0; 3],
+            &[
+                ([0.4, 0.9, 0.8], NONE, [0.4, 0.9, 0.8]),
+                ([0.4, 0.9, 0.8], RED, [0.85, 0.9, 0.8]),
+            ],
+        );
+    }
+}
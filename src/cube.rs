@@ -0,0 +1,261 @@
+use std::{ops::RangeInclusive, path::Path, str::SplitWhitespace};
+
+use derive_more::Display;
+
+/// Sizes up to 65 are what grading tools export. Larger tables would take
+/// up a lot of memory for little gain.
+pub const SIZES: RangeInclusive<usize> = 2..=65;
+
+/// A 3D LUT from an Adobe or Resolve `.cube` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut {
+    pub title: Option<String>,
+    /// Entries along each axis
+    pub size: usize,
+    /// The input values that map to the first and last entries
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    /// Output colors, with the red input changing fastest, then green, then
+    /// blue
+    pub table: Vec<[f32; 3]>,
+}
+
+/// What is wrong with a `.cube` file, and on which line
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display("line {line}: {message}")]
+pub struct ParseError {
+    /// Counted from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::error::Error for ParseError {}
+
+impl Lut {
+    pub fn load(path: &Path) -> crate::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut domain_line = 0;
+        let mut table = Vec::new();
+        let mut line = 0;
+
+        for (index, content) in text.lines().enumerate() {
+            line = index + 1;
+            let error = |message: String| ParseError { line, message };
+            let content = content.trim();
+            if content.starts_with('#') {
+                continue;
+            }
+            let mut words = content.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            match keyword {
+                "TITLE" => {
+                    let quoted = content["TITLE".len()..].trim();
+                    title = Some(quoted.trim_matches('"').to_string());
+                }
+                "LUT_3D_SIZE" => {
+                    if size.is_some() {
+                        return Err(error("LUT_3D_SIZE is given twice".into()));
+                    }
+                    let value = match words.collect::<Vec<_>>()[..] {
+                        [word] => word.parse().ok().filter(|v| SIZES.contains(v)),
+                        _ => None,
+                    }
+                    .ok_or_else(|| {
+                        error(format!(
+                            "LUT_3D_SIZE must be a whole number between {} and {}",
+                            SIZES.start(),
+                            SIZES.end()
+                        ))
+                    })?;
+                    size = Some(value);
+                    table.reserve(value.pow(3));
+                }
+                "LUT_1D_SIZE" => return Err(error("1D LUTs are not supported".into())),
+                "DOMAIN_MIN" => {
+                    domain_min = parse_words(words, line)?;
+                    domain_line = line;
+                }
+                "DOMAIN_MAX" => {
+                    domain_max = parse_words(words, line)?;
+                    domain_line = line;
+                }
+                // Resolve's way of giving the same domain to every channel
+                "LUT_3D_INPUT_RANGE" => {
+                    let [min, max] = parse_words(words, line)?;
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                    domain_line = line;
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) => {
+                    let Some(size) = size else {
+                        return Err(error("Table data before LUT_3D_SIZE".into()));
+                    };
+                    if table.len() == size.pow(3) {
+                        return Err(error(format!(
+                            "More than the {} entries of a size {size} table",
+                            size.pow(3)
+                        )));
+                    }
+                    table.push(parse_words(content.split_whitespace(), line)?);
+                }
+                _ => return Err(error(format!("Unknown keyword {keyword:?}"))),
+            }
+        }
+
+        let Some(size) = size else {
+            return Err(ParseError {
+                line,
+                message: "Missing LUT_3D_SIZE".into(),
+            });
+        };
+        if table.len() != size.pow(3) {
+            return Err(ParseError {
+                line,
+                message: format!(
+                    "Expected {} entries for a size {size} table, found {}",
+                    size.pow(3),
+                    table.len()
+                ),
+            });
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err(ParseError {
+                line: domain_line,
+                message: format!("Empty domain from {domain_min:?} to {domain_max:?}"),
+            });
+        }
+        Ok(Self {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+}
+
+/// Exactly `N` numbers, separated by whitespace
+fn parse_words<const N: usize>(
+    words: SplitWhitespace<'_>,
+    line: usize,
+) -> Result<[f32; N], ParseError> {
+    let words: Vec<&str> = words.collect();
+    if words.len() != N {
+        return Err(ParseError {
+            line,
+            message: format!("Expected {N} values, found {}", words.len()),
+        });
+    }
+    let mut values = [0.0; N];
+    for (value, word) in values.iter_mut().zip(words) {
+        *value = word
+            .parse()
+            .ok()
+            .filter(|v: &f32| v.is_finite())
+            .ok_or_else(|| ParseError {
+                line,
+                message: format!("Invalid number {word:?}"),
+            })?;
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: &str = "# Created by hand
+TITLE \"Identity\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+
+0 0 0
+1 0 0
+0 1 0
+1 1 0
+0 0 1
+1 0 1
+0 1 1
+1 1 1
+";
+
+    #[test]
+    fn test_parse() {
+        let lut = Lut::parse(IDENTITY).unwrap();
+        assert_eq!(lut.title.as_deref(), Some("Identity"));
+        assert_eq!(lut.size, 2);
+        // Red changes fastest
+        let expected: Vec<[f32; 3]> = (0..8)
+            .map(|i: u8| [i & 1, i >> 1 & 1, i >> 2].map(f32::from))
+            .collect();
+        assert_eq!(lut.table, expected);
+    }
+
+    #[test]
+    fn test_resolve_input_range() {
+        let text = IDENTITY.replace("DOMAIN_MAX 1 1 1", "LUT_3D_INPUT_RANGE 0.0 2.0");
+        assert_eq!(
+            Lut::parse(&text).unwrap(),
+            Lut {
+                domain_max: [2.0; 3],
+                ..Lut::parse(IDENTITY).unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_the_line() {
+        let cases = [
+            (IDENTITY.replace("0 1 0\n", "0 1\n"), 9, "Expected 3 values"),
+            (
+                IDENTITY.replace("1 1 0\n", "1 x 0\n"),
+                10,
+                "Invalid number \"x\"",
+            ),
+            (
+                IDENTITY.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 66"),
+                3,
+                "between 2 and 65",
+            ),
+            (
+                IDENTITY.replace("LUT_3D_SIZE 2", "LUT_1D_SIZE 2"),
+                3,
+                "1D LUTs",
+            ),
+            (
+                IDENTITY.replace("DOMAIN_MIN", "DOMAIN_MINIMUM"),
+                4,
+                "Unknown keyword",
+            ),
+            (
+                IDENTITY.replace("DOMAIN_MAX 1 1 1", "DOMAIN_MAX 1 0 1"),
+                5,
+                "Empty domain",
+            ),
+            (format!("{IDENTITY}1 1 1\n"), 15, "More than the 8 entries"),
+            (
+                IDENTITY[..IDENTITY.len() - "1 1 1\n".len()].to_string(),
+                13,
+                "Expected 8 entries",
+            ),
+            ("0 0 0\n".to_string(), 1, "before LUT_3D_SIZE"),
+        ];
+        for (text, line, message) in cases {
+            let error = Lut::parse(&text).unwrap_err();
+            assert_eq!(error.line, line, "{error}");
+            assert!(error.message.contains(message), "{error}");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Entries of the LUT that the curves are evaluated into
+pub const LUT_SIZE: usize = 1024;
+/// How close two control points can get horizontally
+const MIN_SPACING: f32 = 0.01;
+
+/// A tone curve through draggable control points. Inputs and outputs are
+/// values encoded for the output color space, from 0 to 1.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<[f32; 2]>", into = "Vec<[f32; 2]>")]
+pub struct Curve {
+    /// At least two, sorted by input and at least `MIN_SPACING` apart
+    points: Vec<[f32; 2]>,
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self {
+            points: vec![[0.0, 0.0], [1.0, 1.0]],
+        }
+    }
+}
+
+impl TryFrom<Vec<[f32; 2]>> for Curve {
+    type Error = String;
+
+    fn try_from(mut points: Vec<[f32; 2]>) -> Result<Self, Self::Error> {
+        if points.len() < 2 {
+            return Err("A curve needs at least two points".into());
+        }
+        if let Some(point) = points
+            .iter()
+            .find(|point| point.iter().any(|v| !(0.0..=1.0).contains(v)))
+        {
+            return Err(format!("Curve point {point:?} is outside of 0 to 1"));
+        }
+        points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        if let Some([low, high, ..]) = points
+            .windows(2)
+            .find(|pair| pair[1][0] - pair[0][0] < MIN_SPACING)
+        {
+            return Err(format!(
+                "Curve points {low:?} and {high:?} are too close together"
+            ));
+        }
+        Ok(Self { points })
+    }
+}
+
+impl From<Curve> for Vec<[f32; 2]> {
+    fn from(curve: Curve) -> Self {
+        curve.points
+    }
+}
+
+impl Curve {
+    pub fn points(&self) -> &[[f32; 2]] {
+        &self.points
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Adds a control point, unless it is too close to an existing one.
+    /// Returns its index.
+    pub fn insert(&mut self, [x, y]: [f32; 2]) -> Option<usize> {
+        let index = self.points.partition_point(|point| point[0] < x);
+        let too_close = [index.checked_sub(1), Some(index)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.points.get(i))
+            .any(|point| (point[0] - x).abs() < MIN_SPACING);
+        if too_close || !(0.0..=1.0).contains(&x) {
+            return None;
+        }
+        self.points.insert(index, [x, y.clamp(0.0, 1.0)]);
+        Some(index)
+    }
+
+    /// Moves a control point, keeping it between its neighbours
+    pub fn move_point(&mut self, index: usize, [x, y]: [f32; 2]) {
+        let low = index
+            .checked_sub(1)
+            .map_or(0.0, |i| self.points[i][0] + MIN_SPACING);
+        let high = self
+            .points
+            .get(index + 1)
+            .map_or(1.0, |point| point[0] - MIN_SPACING);
+        self.points[index] = [x.clamp(low, high), y.clamp(0.0, 1.0)];
+    }
+
+    /// Removes a control point, as long as two are left
+    pub fn remove(&mut self, index: usize) {
+        if self.points.len() > 2 {
+            self.points.remove(index);
+        }
+    }
+
+    pub fn evaluate(&self, x: f32) -> f32 {
+        self.evaluate_with(&self.tangents(), x)
+    }
+
+    /// The curve at `count` evenly spaced inputs from 0 to 1
+    pub fn sample(&self, count: usize) -> Vec<f32> {
+        let tangents = self.tangents();
+        (0..count)
+            .map(|i| self.evaluate_with(&tangents, i as f32 / (count - 1).max(1) as f32))
+            .collect()
+    }
+
+    /// A cubic Hermite spline through the points, flat outside of them
+    fn evaluate_with(&self, tangents: &[f32], x: f32) -> f32 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if x <= first[0] {
+            return first[1];
+        }
+        if x >= last[0] {
+            return last[1];
+        }
+        let i = self.points.partition_point(|point| point[0] <= x) - 1;
+        let ([x0, y0], [x1, y1]) = (self.points[i], self.points[i + 1]);
+        let h = x1 - x0;
+        let t = (x - x0) / h;
+        let t2 = t * t;
+        // The Hermite basis functions
+        let h00 = t2.mul_add(2.0_f32.mul_add(t, -3.0), 1.0);
+        let h10 = t * (t - 1.0) * (t - 1.0);
+        let h01 = t2 * (-2.0_f32).mul_add(t, 3.0);
+        let h11 = t2 * (t - 1.0);
+        let slopes = h10.mul_add(tangents[i], h11 * tangents[i + 1]);
+        h00.mul_add(y0, h01.mul_add(y1, h * slopes)).clamp(0.0, 1.0)
+    }
+
+    /// Steffen's tangents, which keep the spline monotonic between the
+    /// points so it never overshoots them
+    fn tangents(&self) -> Vec<f32> {
+        let widths: Vec<f32> = self.points.windows(2).map(|p| p[1][0] - p[0][0]).collect();
+        let slopes: Vec<f32> = self
+            .points
+            .windows(2)
+            .zip(&widths)
+            .map(|(p, width)| (p[1][1] - p[0][1]) / width)
+            .collect();
+        let inner = (1..slopes.len()).map(|i| {
+            let (s0, s1) = (slopes[i - 1], slopes[i]);
+            let (h0, h1) = (widths[i - 1], widths[i]);
+            let p = s0.mul_add(h1, s1 * h0) / (h0 + h1);
+            let limit = s0.abs().min(s1.abs()).min(0.5 * p.abs());
+            (s0.signum() + s1.signum()) * limit
+        });
+        std::iter::once(slopes[0])
+            .chain(inner)
+            .chain(std::iter::once(slopes[slopes.len() - 1]))
+            .collect()
+    }
+}
+
+/// Which curve of the `Curves` is edited
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display)]
+pub enum CurveChannel {
+    #[default]
+    Luminance,
+    Red,
+    Green,
+    Blue,
+}
+
+impl CurveChannel {
+    pub const ALL: [Self; 4] = [Self::Luminance, Self::Red, Self::Green, Self::Blue];
+}
+
+/// A curve per color channel, followed by one on the luminance
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Curves {
+    pub luminance: Curve,
+    pub red: Curve,
+    pub green: Curve,
+    pub blue: Curve,
+}
+
+impl Curves {
+    pub const fn get(&self, channel: CurveChannel) -> &Curve {
+        match channel {
+            CurveChannel::Luminance => &self.luminance,
+            CurveChannel::Red => &self.red,
+            CurveChannel::Green => &self.green,
+            CurveChannel::Blue => &self.blue,
+        }
+    }
+
+    pub const fn get_mut(&mut self, channel: CurveChannel) -> &mut Curve {
+        match channel {
+            CurveChannel::Luminance => &mut self.luminance,
+            CurveChannel::Red => &mut self.red,
+            CurveChannel::Green => &mut self.green,
+            CurveChannel::Blue => &mut self.blue,
+        }
+    }
+
+    /// The texels of the LUT texture, with the color curves in red, green
+    /// and blue and the luminance curve in alpha
+    pub fn lut(&self) -> Vec<[f32; 4]> {
+        let [red, green, blue, luminance] =
+            [&self.red, &self.green, &self.blue, &self.luminance].map(|c| c.sample(LUT_SIZE));
+        (0..LUT_SIZE)
+            .map(|i| [red[i], green[i], blue[i], luminance[i]])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[[f32; 2]]) -> Curve {
+        Curve::try_from(points.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_identity_lut() {
+        let lut = Curves::default().lut();
+        assert_eq!(lut.len(), LUT_SIZE);
+        for (i, texel) in lut.iter().enumerate() {
+            let x = i as f32 / (LUT_SIZE - 1) as f32;
+            assert!(texel.iter().all(|v| (v - x).abs() < 1e-6), "{i} {texel:?}");
+        }
+    }
+
+    #[test]
+    fn test_passes_through_points_without_overshooting() {
+        let points = [[0.0, 0.1], [0.25, 0.2], [0.3, 0.8], [0.7, 0.85], [1.0, 1.0]];
+        let curve = curve(&points);
+        for [x, y] in points {
+            assert!((curve.evaluate(x) - y).abs() < 1e-6, "{x} {y}");
+        }
+        let samples = curve.sample(1000);
+        assert!(samples.windows(2).all(|pair| pair[1] >= pair[0]));
+    }
+
+    #[test]
+    fn test_invalid_points() {
+        assert!(Curve::try_from(vec![[0.5, 0.5]]).is_err());
+        assert!(Curve::try_from(vec![[0.0, 0.0], [1.0, 1.5]]).is_err());
+        assert!(Curve::try_from(vec![[0.0, 0.0], [0.5, 0.2], [0.501, 0.3]]).is_err());
+        let unsorted = curve(&[[1.0, 1.0], [0.0, 0.0]]);
+        assert!(unsorted.is_identity());
+    }
+
+    #[test]
+    fn test_moved_points_keep_their_order() {
+        let mut curve = Curve::default();
+        let index = curve.insert([0.5, 0.7]).unwrap();
+        assert_eq!(curve.insert([0.505, 0.2]), None);
+        curve.move_point(index, [1.2, -0.3]);
+        let [x, y] = curve.points()[index];
+        assert!(
+            (x - (1.0 - MIN_SPACING)).abs() < 1e-6 && y == 0.0,
+            "{x} {y}"
+        );
+        curve.remove(index);
+        curve.remove(0);
+        assert!(curve.is_identity());
+    }
+}
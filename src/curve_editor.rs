@@ -0,0 +1,207 @@
+use iced::{
+    Color, Point, Rectangle, Renderer, Size, Theme, mouse,
+    widget::canvas::{self, Frame, Geometry, Path, Stroke},
+};
+
+use crate::{
+    curve::{Curve, CurveChannel},
+    ui::Message,
+};
+
+/// Of the canvas the curve is drawn on
+pub const SIZE: Size = Size::new(120.0, 120.0);
+/// How far from a control point, in pixels, a click still grabs it
+const HANDLE_RADIUS: f32 = 6.0;
+/// Segments the curve is drawn with
+const DRAW_SAMPLES: usize = 128;
+
+/// Draws the curve of a channel over a grid and lets its control points be
+/// dragged. Clicking next to the curve adds a point, right clicking one
+/// removes it.
+pub struct CurveEditor<'a> {
+    pub curve: &'a Curve,
+    pub channel: CurveChannel,
+}
+
+#[derive(Debug, Default)]
+pub struct State {
+    /// The control point being dragged
+    dragging: Option<usize>,
+    /// Whether the curve changed since the mouse button went down
+    edited: bool,
+}
+
+impl CurveEditor<'_> {
+    fn to_screen(bounds: Rectangle, [x, y]: [f32; 2]) -> Point {
+        Point::new(x * bounds.width, (1.0 - y) * bounds.height)
+    }
+
+    fn to_curve(bounds: Rectangle, position: Point) -> [f32; 2] {
+        [
+            (position.x / bounds.width).clamp(0.0, 1.0),
+            (1.0 - position.y / bounds.height).clamp(0.0, 1.0),
+        ]
+    }
+
+    /// The control point under the cursor, if any
+    fn point_at(&self, bounds: Rectangle, position: Point) -> Option<usize> {
+        self.curve
+            .points()
+            .iter()
+            .position(|&point| Self::to_screen(bounds, point).distance(position) <= HANDLE_RADIUS)
+    }
+
+    const fn color(&self) -> Color {
+        match self.channel {
+            CurveChannel::Luminance => Color::WHITE,
+            CurveChannel::Red => Color::from_rgb(0.9, 0.3, 0.3),
+            CurveChannel::Green => Color::from_rgb(0.3, 0.8, 0.3),
+            CurveChannel::Blue => Color::from_rgb(0.4, 0.5, 1.0),
+        }
+    }
+
+    fn publish(state: &mut State, curve: Curve) -> canvas::Action<Message> {
+        state.edited = true;
+        canvas::Action::publish(Message::Curve(curve)).and_capture()
+    }
+}
+
+impl canvas::Program<Message> for CurveEditor<'_> {
+    type State = State;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: &iced::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> Option<canvas::Action<Message>> {
+        let iced::Event::Mouse(event) = event else {
+            return None;
+        };
+        match event {
+            mouse::Event::ButtonPressed(mouse::Button::Left) => {
+                let position = cursor.position_in(bounds)?;
+                if let Some(index) = self.point_at(bounds, position) {
+                    state.dragging = Some(index);
+                    return Some(canvas::Action::capture());
+                }
+                let mut curve = self.curve.clone();
+                state.dragging = Some(curve.insert(Self::to_curve(bounds, position))?);
+                Some(Self::publish(state, curve))
+            }
+            mouse::Event::ButtonPressed(mouse::Button::Right) => {
+                let index = self.point_at(bounds, cursor.position_in(bounds)?)?;
+                let mut curve = self.curve.clone();
+                curve.remove(index);
+                Some(Self::publish(state, curve))
+            }
+            mouse::Event::CursorMoved { .. } => {
+                let index = state.dragging?;
+                let position = cursor.position_from(bounds.position())?;
+                let mut curve = self.curve.clone();
+                curve.move_point(index, Self::to_curve(bounds, position));
+                Some(Self::publish(state, curve))
+            }
+            mouse::Event::ButtonReleased(_) => {
+                state.dragging = None;
+                std::mem::take(&mut state.edited)
+                    .then(|| canvas::Action::publish(Message::SaveSidecar))
+            }
+            _ => None,
+        }
+    }
+
+    fn draw(
+        &self,
+        state: &Self::State,
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let bounds = Rectangle::with_size(bounds.size());
+        frame.fill_rectangle(Point::ORIGIN, bounds.size(), Color::from_rgb(0.1, 0.1, 0.1));
+
+        let grid = Stroke::default()
+            .with_width(1.0)
+            .with_color(Color::from_rgb(0.25, 0.25, 0.25));
+        for i in 1..4 {
+            let t = i as f32 / 4.0;
+            frame.stroke(
+                &Path::line(
+                    Self::to_screen(bounds, [t, 0.0]),
+                    Self::to_screen(bounds, [t, 1.0]),
+                ),
+                grid,
+            );
+            frame.stroke(
+                &Path::line(
+                    Self::to_screen(bounds, [0.0, t]),
+                    Self::to_screen(bounds, [1.0, t]),
+                ),
+                grid,
+            );
+        }
+        frame.stroke(
+            &Path::line(
+                Self::to_screen(bounds, [0.0, 0.0]),
+                Self::to_screen(bounds, [1.0, 1.0]),
+            ),
+            grid,
+        );
+
+        let samples = self.curve.sample(DRAW_SAMPLES);
+        let line = Path::new(|builder| {
+            for (i, y) in samples.iter().enumerate() {
+                let x = i as f32 / (DRAW_SAMPLES - 1) as f32;
+                let point = Self::to_screen(bounds, [x, *y]);
+                if i == 0 {
+                    builder.move_to(point);
+                } else {
+                    builder.line_to(point);
+                }
+            }
+        });
+        frame.stroke(
+            &line,
+            Stroke::default().with_width(2.0).with_color(self.color()),
+        );
+
+        for (i, &point) in self.curve.points().iter().enumerate() {
+            let handle = Path::circle(Self::to_screen(bounds, point), HANDLE_RADIUS / 2.0);
+            if state.dragging == Some(i) {
+                frame.fill(&handle, self.color());
+            } else {
+                frame.fill(&handle, Color::from_rgb(0.1, 0.1, 0.1));
+                frame.stroke(
+                    &handle,
+                    Stroke::default().with_width(1.5).with_color(self.color()),
+                );
+            }
+        }
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        state: &Self::State,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        let over_point = cursor
+            .position_in(bounds)
+            .and_then(|position| self.point_at(bounds, position))
+            .is_some();
+        if state.dragging.is_some() {
+            mouse::Interaction::Grabbing
+        } else if over_point {
+            mouse::Interaction::Grab
+        } else if cursor.is_over(bounds) {
+            mouse::Interaction::Crosshair
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
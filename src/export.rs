@@ -0,0 +1,286 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use derive_more::Display;
+use iced::futures::channel::oneshot;
+use image::ImageEncoder;
+use tiff::{encoder::colortype, tags::Tag};
+use tracing::warn;
+
+use crate::{headless::Headless, icc, program::Program};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, clap::ValueEnum)]
+pub enum ExportFormat {
+    #[display("JPEG")]
+    Jpeg,
+    #[display("PNG")]
+    Png,
+    #[display("TIFF")]
+    Tiff,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 3] = [Self::Jpeg, Self::Png, Self::Tiff];
+
+    pub const fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Jpeg => &["jpg", "jpeg"],
+            Self::Png => &["png"],
+            Self::Tiff => &["tif", "tiff"],
+        }
+    }
+
+    /// Picks the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extensions().contains(&extension.as_str()))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, clap::ValueEnum)]
+pub enum BitDepth {
+    #[default]
+    #[display("8 bit")]
+    #[value(name = "8")]
+    Eight,
+    #[display("16 bit")]
+    #[value(name = "16")]
+    Sixteen,
+}
+
+impl BitDepth {
+    pub const ALL: [Self; 2] = [Self::Eight, Self::Sixteen];
+}
+
+/// The export choices that don't depend on the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSettings {
+    /// Ignored for JPEG, which only has 8 bits
+    pub bit_depth: BitDepth,
+    pub jpeg_quality: u8,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            bit_depth: BitDepth::default(),
+            jpeg_quality: 90,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    pub settings: ExportSettings,
+}
+
+/// Processes the image at full resolution, after cropping, and writes it to
+/// the destination in the options, tagged with the profile of the output
+/// color space.
+pub fn export(
+    headless: &Headless,
+    program: &Program,
+    options: &ExportOptions,
+) -> crate::Result<()> {
+    let rendered = headless.render(program, program.export_size())?;
+    let profile = icc::profile(program.settings.output_color_space);
+    encode(
+        &image::DynamicImage::ImageRgba32F(rendered),
+        options,
+        profile,
+    )
+}
+
+/// The outcome of an export in the background, along with the GPU device
+/// it ran on, for the next one to reuse.
+#[derive(Debug, Clone)]
+pub struct Exported {
+    pub headless: Option<Arc<Headless>>,
+    pub result: Result<PathBuf, String>,
+}
+
+/// Exports on a background thread, on the given GPU device or on a new one
+/// if there is none yet.
+pub async fn export_in_background(
+    headless: Option<Arc<Headless>>,
+    program: Program,
+    options: ExportOptions,
+) -> Exported {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let headless = headless.map_or_else(|| Headless::new().map(Arc::new), Ok);
+        let result = headless
+            .as_ref()
+            .map_err(ToString::to_string)
+            .and_then(|headless| {
+                export(headless, &program, &options)
+                    .map(|()| options.path)
+                    .map_err(|e| e.to_string())
+            });
+        let _ = sender.send(Exported {
+            headless: headless.ok(),
+            result,
+        });
+    });
+    receiver.await.unwrap_or_else(|_| Exported {
+        headless: None,
+        result: Err("Export thread panicked".to_string()),
+    })
+}
+
+fn encode(
+    image: &image::DynamicImage,
+    options: &ExportOptions,
+    icc_profile: Vec<u8>,
+) -> crate::Result<()> {
+    let file = BufWriter::new(File::create(&options.path)?);
+    // The processing shader writes an opaque alpha channel
+    match (options.format, options.settings.bit_depth) {
+        (ExportFormat::Jpeg, _) => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
+                file,
+                options.settings.jpeg_quality,
+            );
+            write_with_profile(&image.to_rgb8().into(), encoder, icc_profile)
+        }
+        (ExportFormat::Png, BitDepth::Eight) => write_with_profile(
+            &image.to_rgb8().into(),
+            image::codecs::png::PngEncoder::new(file),
+            icc_profile,
+        ),
+        (ExportFormat::Png, BitDepth::Sixteen) => write_with_profile(
+            &image.to_rgb16().into(),
+            image::codecs::png::PngEncoder::new(file),
+            icc_profile,
+        ),
+        // The TIFF encoder of the image crate can't embed a profile
+        (ExportFormat::Tiff, BitDepth::Eight) => {
+            let rgb = image.to_rgb8();
+            write_tiff::<colortype::RGB8>(file, rgb.dimensions(), &rgb, &icc_profile)
+        }
+        (ExportFormat::Tiff, BitDepth::Sixteen) => {
+            let rgb = image.to_rgb16();
+            write_tiff::<colortype::RGB16>(file, rgb.dimensions(), &rgb, &icc_profile)
+        }
+    }
+}
+
+#[allow(clippy::cognitive_complexity)]
+fn write_with_profile(
+    image: &image::DynamicImage,
+    mut encoder: impl ImageEncoder,
+    icc_profile: Vec<u8>,
+) -> crate::Result<()> {
+    if let Err(e) = encoder.set_icc_profile(icc_profile) {
+        warn!("Exporting without a color profile: {e}");
+    }
+    image.write_with_encoder(encoder)?;
+    Ok(())
+}
+
+fn write_tiff<C: colortype::ColorType>(
+    file: BufWriter<File>,
+    (width, height): (u32, u32),
+    data: &[C::Inner],
+    icc_profile: &[u8],
+) -> crate::Result<()>
+where
+    [C::Inner]: tiff::encoder::TiffValue,
+{
+    let mut encoder = tiff::encoder::TiffEncoder::new(file)?;
+    let mut image = encoder.new_image::<C>(width, height)?;
+    image.encoder().write_tag(Tag::IccProfile, icc_profile)?;
+    image.write_data(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        let cases = [
+            ("out.jpg", Some(ExportFormat::Jpeg)),
+            ("out.JPEG", Some(ExportFormat::Jpeg)),
+            ("dir/out.png", Some(ExportFormat::Png)),
+            ("out.tif", Some(ExportFormat::Tiff)),
+            ("out.webp", None),
+            ("out", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(ExportFormat::from_path(Path::new(path)), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_16_bit_export_keeps_precision() {
+        let path = std::env::temp_dir().join("wgpu_compute_test_export.png");
+        let gradient = image::ImageBuffer::from_fn(256, 1, |x, _| {
+            let v = (32_768.0 + x as f32) / 65535.0;
+            image::Rgba([v, v, v, 1.0])
+        });
+        let options = ExportOptions {
+            path: path.clone(),
+            format: ExportFormat::Png,
+            settings: ExportSettings {
+                bit_depth: BitDepth::Sixteen,
+                ..Default::default()
+            },
+        };
+        encode(&gradient.into(), &options, Vec::new()).unwrap();
+        let decoded = image::open(&path).unwrap().into_rgb16();
+        std::fs::remove_file(&path).unwrap();
+
+        let first = decoded.get_pixel(0, 0)[0];
+        assert_eq!(decoded.get_pixel(255, 0)[0] - first, 255);
+    }
+
+    /// The image crate looks for the profile of TIFFs under a tag number the
+    /// tiff crate reports by name, so it never finds it
+    fn embedded_profile(path: &Path, format: ExportFormat) -> Option<Vec<u8>> {
+        if format == ExportFormat::Tiff {
+            let mut decoder = tiff::decoder::Decoder::new(File::open(path).unwrap()).unwrap();
+            return decoder.get_tag_u8_vec(Tag::IccProfile).ok();
+        }
+        let mut decoder = image::ImageReader::open(path)
+            .unwrap()
+            .into_decoder()
+            .unwrap();
+        image::ImageDecoder::icc_profile(&mut decoder).unwrap()
+    }
+
+    #[test]
+    fn test_embeds_icc_profile() {
+        let profile = icc::profile(crate::color::OutputColorSpace::DisplayP3);
+        for format in ExportFormat::ALL {
+            let path = std::env::temp_dir().join(format!(
+                "wgpu_compute_test_profile.{}",
+                format.extensions()[0]
+            ));
+            let options = ExportOptions {
+                path: path.clone(),
+                format,
+                settings: ExportSettings::default(),
+            };
+            encode(
+                &image::DynamicImage::new_rgba32f(4, 4),
+                &options,
+                profile.clone(),
+            )
+            .unwrap();
+            let embedded = embedded_profile(&path, format);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(embedded, Some(profile.clone()), "{format}");
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use derive_more::Display;
+
+/// How many leading bytes of a file are needed to tell the formats apart.
+const HEADER_LEN: u64 = 16;
+
+/// Raw formats with a signature of their own, rather than a bare TIFF header
+const RAW_SIGNATURES: [&[u8]; 7] = [
+    b"FUJIFILMCCD-RAW", // RAF
+    b"IIRO",            // ORF
+    b"IIRS",            // ORF
+    b"MMOR",            // ORF
+    b"IIU\0",           // RW2
+    b"\0MRM",           // MRW
+    b"FOVb",            // X3F
+];
+
+/// Extensions of camera raws, which the image crate doesn't know about
+const RAW_EXTENSIONS: [&str; 24] = [
+    "3fr", "ari", "arw", "cr2", "crw", "dcr", "dcs", "dng", "erf", "iiq", "kdc", "mef", "mos",
+    "mrw", "nef", "nrw", "orf", "pef", "raf", "raw", "rw2", "rwl", "srw", "x3f",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A camera raw file for rawloader
+    Raw,
+    /// A TIFF container, which holds either sensor data (NEF, ARW, DNG, PEF)
+    /// or a regular image
+    Tiff,
+    /// Anything else the image crate can decode
+    Image(image::ImageFormat),
+}
+
+#[derive(Debug, Display)]
+#[display("Unsupported image format: {}", _0.display())]
+pub struct UnsupportedFormat(pub PathBuf);
+
+impl std::error::Error for UnsupportedFormat {}
+
+/// Detects the format from the magic bytes at the start of the file.
+pub fn detect(path: &Path) -> crate::Result<Format> {
+    let mut header = Vec::new();
+    File::open(path)?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)?;
+    detect_header(&header).ok_or_else(|| UnsupportedFormat(path.to_path_buf()).into())
+}
+
+/// Whether the file looks like an image we can decode. Only the extension is
+/// checked, so listing a folder doesn't read every file; a file whose
+/// content doesn't match fails to decode with `UnsupportedFormat`.
+pub fn is_supported(path: &Path) -> bool {
+    has_supported_extension(path) && path.is_file()
+}
+
+fn has_supported_extension(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|e| e.to_str()) else {
+        return false;
+    };
+    let extension = extension.to_ascii_lowercase();
+    RAW_EXTENSIONS.contains(&extension.as_str())
+        || image::ImageFormat::from_extension(&extension)
+            .is_some_and(|format| format.reading_enabled())
+}
+
+fn detect_header(header: &[u8]) -> Option<Format> {
+    if RAW_SIGNATURES
+        .iter()
+        .any(|signature| header.starts_with(signature))
+    {
+        return Some(Format::Raw);
+    }
+    // Canon CRW is a CIFF container
+    if header.get(6..14) == Some(b"HEAPCCDR") {
+        return Some(Format::Raw);
+    }
+    if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        // Canon CR2 marks itself right after the TIFF header
+        if header.get(8..10) == Some(b"CR") {
+            return Some(Format::Raw);
+        }
+        return Some(Format::Tiff);
+    }
+    image::guess_format(header).ok().map(Format::Image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_header() {
+        let cases: [(&[u8], Option<Format>); 7] = [
+            (b"II*\0\x10\0\0\0CR\x02\0", Some(Format::Raw)),
+            (b"II*\0\x08\0\0\0\0\0", Some(Format::Tiff)),
+            (b"MM\0*\0\0\0\x08\0\0", Some(Format::Tiff)),
+            (b"FUJIFILMCCD-RAW 0201", Some(Format::Raw)),
+            (b"IIU\0\x08\0\0\0", Some(Format::Raw)),
+            (
+                b"\xff\xd8\xff\xe1\0\0",
+                Some(Format::Image(image::ImageFormat::Jpeg)),
+            ),
+            (b"not an image", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(detect_header(header), expected, "{header:?}");
+        }
+    }
+
+    #[test]
+    fn test_supported_extensions() {
+        for name in ["IMG_0001.CR2", "a.dng", "a.jpeg", "a.png", "a.tif"] {
+            assert!(has_supported_extension(Path::new(name)), "{name}");
+        }
+        for name in ["a.txt", "a.xmp", "a.cube", "cr2", "a"] {
+            assert!(!has_supported_extension(Path::new(name)), "{name}");
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    compute::{
+        self,
+        demosaic::DemosaicShader,
+        downsample::DownsampleShader,
+        hsl::HslShader,
+        processing::{self, ProcessingShader},
+    },
+    primitive::{self, XTRANS_CFA_SIZE},
+    program::Program,
+    renderer::Textures,
+    util::Tof32,
+};
+
+/// A GPU device of our own, for running the processing pipeline outside of
+/// the shader widget.
+#[derive(Debug)]
+pub struct Headless {
+    adapter_name: String,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+}
+
+impl Headless {
+    pub fn new() -> crate::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+        // Honours WGPU_ADAPTER_NAME and WGPU_POWER_PREF, so software
+        // adapters like llvmpipe can be picked by name
+        let adapter = pollster::block_on(wgpu::util::initialize_adapter_from_env_or_default(
+            &instance, None,
+        ))?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+                label: Some("headless_device"),
+                // Full resolution exports need the largest textures the
+                // adapter supports
+                required_limits: adapter.limits(),
+                ..Default::default()
+            }))?;
+        Ok(Self {
+            adapter_name: adapter.get_info().name,
+            device,
+            queue,
+        })
+    }
+
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    /// Runs demosaic, downsample, processing and the HSL pass on the
+    /// program's image with its current settings, scaled to `output_size`.
+    pub fn render(
+        &self,
+        program: &Program,
+        output_size: iced::Size<u32>,
+    ) -> crate::Result<image::Rgba32FImage> {
+        let image = program.image.as_ref();
+        let uniforms = program.uniforms(output_size.to_f32());
+        let mut textures = Textures::new(&self.device, image, output_size);
+        compute::write_texture(&self.queue, &textures.full_texture, image)?;
+        processing::write_curves(&self.queue, &textures.curve_lut, &program.settings.curves);
+        if let Some(lut) = program.lut() {
+            textures.lut = processing::create_lut_texture(&self.device, lut.size as u32);
+            processing::write_lut(&self.queue, &textures.lut, lut);
+        }
+
+        let uniforms_buffer = primitive::create_uniforms_buffer(&self.device);
+        self.queue.write_buffer(
+            &uniforms_buffer,
+            0,
+            bytemuck::bytes_of(&uniforms.to_raw(output_size.to_f32())),
+        );
+        let demosaic_shader = if uniforms.cfa_size == XTRANS_CFA_SIZE {
+            DemosaicShader::compile_xtrans(&self.device, &uniforms_buffer, &textures)
+        } else {
+            DemosaicShader::compile(
+                &self.device,
+                &uniforms_buffer,
+                &textures,
+                program.settings.demosaic_algorithm,
+            )
+        };
+        let downsample_shader =
+            DownsampleShader::compile(&self.device, &uniforms_buffer, &textures);
+        let processing_shader =
+            ProcessingShader::compile(&self.device, &uniforms_buffer, &textures);
+        let hsl_shader = HslShader::compile(&self.device, &uniforms_buffer, &textures);
+        let encode_shader =
+            ProcessingShader::compile_encode(&self.device, &uniforms_buffer, &textures);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("headless.render.encoder"),
+            });
+        compute::enqueue_workload(&mut encoder, &demosaic_shader);
+        compute::enqueue_workload(&mut encoder, &downsample_shader);
+        compute::enqueue_workload(&mut encoder, &processing_shader);
+        compute::enqueue_workload(&mut encoder, &hsl_shader);
+        compute::enqueue_workload(&mut encoder, &encode_shader);
+        self.queue.submit(Some(encoder.finish()));
+
+        let data = compute::read_texture(&self.device, &self.queue, &textures.output_texture)?;
+        image::Rgba32FImage::from_raw(output_size.width, output_size.height, data)
+            .ok_or_else(|| "Output texture has an unexpected size".into())
+    }
+}
@@ -0,0 +1,274 @@
+use std::ops::RangeInclusive;
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// In degrees of `OKLCh` hue
+pub const HUE_RANGE: RangeInclusive<f32> = -30.0..=30.0;
+/// As a fraction of the chroma, which -1 removes entirely
+pub const SATURATION_RANGE: RangeInclusive<f32> = -1.0..=1.0;
+/// In stops
+pub const LUMINANCE_RANGE: RangeInclusive<f32> = -1.0..=1.0;
+/// Like saturation, but weighted towards muted colors
+pub const VIBRANCE_RANGE: RangeInclusive<f32> = -1.0..=1.0;
+
+/// `OKLCh` hues, in degrees, of the sRGB colors each band is centred on, in
+/// the order of `HueBand::ALL`. The HSL shader has a copy.
+#[cfg(test)]
+const BAND_HUES: [f32; 8] = [29.0, 53.0, 110.0, 142.0, 195.0, 264.0, 294.0, 328.0];
+/// Below this chroma the hue is mostly noise, so the bands fade out
+#[cfg(test)]
+const NEUTRAL_CHROMA: f32 = 0.02;
+/// Colors with at least this chroma count as saturated for the vibrance
+#[cfg(test)]
+const VIBRANT_CHROMA: f32 = 0.25;
+
+/// The hues that can be adjusted separately. Colors in between are adjusted
+/// by a blend of the two bands around them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display)]
+pub enum HueBand {
+    #[default]
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Aqua,
+    Blue,
+    Purple,
+    Magenta,
+}
+
+impl HueBand {
+    pub const ALL: [Self; 8] = [
+        Self::Red,
+        Self::Orange,
+        Self::Yellow,
+        Self::Green,
+        Self::Aqua,
+        Self::Blue,
+        Self::Purple,
+        Self::Magenta,
+    ];
+}
+
+/// How the colors of one hue band are changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HslAdjustment {
+    pub hue: f32,
+    pub saturation: f32,
+    pub luminance: f32,
+}
+
+/// An adjustment per hue band. They are made in `OKLCh`, where hue and chroma
+/// change without changing the perceived lightness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hsl {
+    pub red: HslAdjustment,
+    pub orange: HslAdjustment,
+    pub yellow: HslAdjustment,
+    pub green: HslAdjustment,
+    pub aqua: HslAdjustment,
+    pub blue: HslAdjustment,
+    pub purple: HslAdjustment,
+    pub magenta: HslAdjustment,
+}
+
+impl Hsl {
+    pub const fn get(&self, band: HueBand) -> &HslAdjustment {
+        match band {
+            HueBand::Red => &self.red,
+            HueBand::Orange => &self.orange,
+            HueBand::Yellow => &self.yellow,
+            HueBand::Green => &self.green,
+            HueBand::Aqua => &self.aqua,
+            HueBand::Blue => &self.blue,
+            HueBand::Purple => &self.purple,
+            HueBand::Magenta => &self.magenta,
+        }
+    }
+
+    pub const fn get_mut(&mut self, band: HueBand) -> &mut HslAdjustment {
+        match band {
+            HueBand::Red => &mut self.red,
+            HueBand::Orange => &mut self.orange,
+            HueBand::Yellow => &mut self.yellow,
+            HueBand::Green => &mut self.green,
+            HueBand::Aqua => &mut self.aqua,
+            HueBand::Blue => &mut self.blue,
+            HueBand::Purple => &mut self.purple,
+            HueBand::Magenta => &mut self.magenta,
+        }
+    }
+
+    /// One value of every band, in the order of `HueBand::ALL`
+    pub fn bands(&self, value: impl Fn(&HslAdjustment) -> f32) -> [f32; 8] {
+        HueBand::ALL.map(|band| value(self.get(band)))
+    }
+
+    /// The adjustment of the two bands around the hue, blended by how close
+    /// it is to each, like `hue_band` in the HSL shader
+    #[cfg(test)]
+    fn at_hue(&self, hue: f32) -> HslAdjustment {
+        let hue = hue.rem_euclid(360.0);
+        // Hues past the last band or before the first lie between magenta
+        // and red
+        let low = BAND_HUES.iter().rposition(|&band| band <= hue).unwrap_or(7);
+        let high = (low + 1) % 8;
+        let wrap = |wraps: bool| if wraps { 360.0 } else { 0.0 };
+        let width = BAND_HUES[high] - BAND_HUES[low] + wrap(high == 0);
+        let offset = hue - BAND_HUES[low] + wrap(hue < BAND_HUES[low]);
+        let t = smoothstep(offset / width);
+        let [a, b] = [low, high].map(|band| *self.get(HueBand::ALL[band]));
+        HslAdjustment {
+            hue: (b.hue - a.hue).mul_add(t, a.hue),
+            saturation: (b.saturation - a.saturation).mul_add(t, a.saturation),
+            luminance: (b.luminance - a.luminance).mul_add(t, a.luminance),
+        }
+    }
+
+    /// Adjusts an XYZ color, like `adjust_colors` in the HSL shader
+    #[cfg(test)]
+    fn adjust(&self, saturation: f32, vibrance: f32, xyz: [f32; 3]) -> [f32; 3] {
+        let [lightness, a, b] = oklab::from_xyz(xyz);
+        let mut chroma = a.hypot(b);
+        let hue = b.atan2(a).to_degrees();
+        let band = self.at_hue(hue);
+        let strength = smoothstep(chroma / NEUTRAL_CHROMA);
+
+        let hue = band.hue.mul_add(strength, hue).to_radians();
+        // OKLab lightness follows the cube root of luminance
+        let lightness = lightness * (band.luminance * strength / 3.0).exp2();
+        chroma *= (1.0 + band.saturation).max(0.0) * (1.0 + saturation).max(0.0);
+        let muted = 1.0 - smoothstep(chroma / VIBRANT_CHROMA);
+        chroma *= vibrance.mul_add(muted, 1.0).max(0.0);
+
+        oklab::to_xyz([lightness, chroma * hue.cos(), chroma * hue.sin()])
+    }
+}
+
+/// Hermite smoothing of `t`, like `smoothstep(0.0, 1.0, t)` in WGSL
+#[cfg(test)]
+fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * 2.0_f32.mul_add(-t, 3.0)
+}
+
+/// Ottosson's conversions between XYZ and `OKLab`, as in the HSL shader
+#[cfg(test)]
+mod oklab {
+    type Matrix = [[f32; 3]; 3];
+
+    const XYZ_TO_LMS: Matrix = [
+        [0.818_933, 0.361_866_74, -0.128_859_71],
+        [0.032_984_544, 0.929_311_9, 0.036_145_64],
+        [0.048_200_3, 0.264_366_27, 0.633_851_7],
+    ];
+    const LMS_TO_OKLAB: Matrix = [
+        [0.210_454_26, 0.793_617_8, -0.004_072_047],
+        [1.977_998_5, -2.428_592_2, 0.450_593_7],
+        [0.025_904_037, 0.782_771_77, -0.808_675_77],
+    ];
+    const OKLAB_TO_LMS: Matrix = [
+        [1.0, 0.396_337_78, 0.215_803_76],
+        [1.0, -0.105_561_346, -0.063_854_17],
+        [1.0, -0.089_484_18, -1.291_485_5],
+    ];
+    const LMS_TO_XYZ: Matrix = [
+        [1.227_014, -0.557_8, 0.281_256_15],
+        [-0.040_580_18, 1.112_256_9, -0.071_676_68],
+        [-0.076_381_28, -0.421_481_97, 1.586_163_2],
+    ];
+
+    fn multiply(m: Matrix, v: [f32; 3]) -> [f32; 3] {
+        m.map(|row| row[0].mul_add(v[0], row[1].mul_add(v[1], row[2] * v[2])))
+    }
+
+    pub fn from_xyz(xyz: [f32; 3]) -> [f32; 3] {
+        multiply(LMS_TO_OKLAB, multiply(XYZ_TO_LMS, xyz).map(f32::cbrt))
+    }
+
+    pub fn to_xyz(lab: [f32; 3]) -> [f32; 3] {
+        multiply(LMS_TO_XYZ, multiply(OKLAB_TO_LMS, lab).map(|v| v * v * v))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::cognitive_complexity)]
+mod tests {
+    use super::*;
+
+    /// A color of the given `OKLCh` lightness, chroma and hue
+    fn oklch(lightness: f32, chroma: f32, hue: f32) -> [f32; 3] {
+        let hue = hue.to_radians();
+        oklab::to_xyz([lightness, chroma * hue.cos(), chroma * hue.sin()])
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4),
+            "{a:?} {b:?}"
+        );
+    }
+
+    #[test]
+    fn test_defaults_are_an_identity() {
+        for xyz in [
+            [0.0, 0.0, 0.0],
+            [0.950_47, 1.0, 1.088_83],
+            [0.412_4, 0.212_6, 0.019_3],
+            [0.05, 0.1, 0.3],
+            [4.0, 3.0, 2.0],
+        ] {
+            assert_close(oklab::to_xyz(oklab::from_xyz(xyz)), xyz);
+            assert_close(Hsl::default().adjust(0.0, 0.0, xyz), xyz);
+        }
+    }
+
+    #[test]
+    fn test_bands_blend_between_their_centres() {
+        let mut hsl = Hsl::default();
+        hsl.red.saturation = 1.0;
+        hsl.magenta.saturation = -1.0;
+        for (band, &hue) in HueBand::ALL.iter().zip(&BAND_HUES) {
+            assert_eq!(hsl.at_hue(hue), *hsl.get(*band), "{band}");
+        }
+        // Halfway from red to orange, and across 0 from magenta to red
+        let halfway = f32::midpoint(BAND_HUES[0], BAND_HUES[1]);
+        assert!((hsl.at_hue(halfway).saturation - 0.5).abs() < 1e-6);
+        let across = (BAND_HUES[7] + BAND_HUES[0] + 360.0) / 2.0;
+        assert!(hsl.at_hue(across).saturation.abs() < 1e-6);
+        assert!(hsl.at_hue(across - 360.0).saturation.abs() < 1e-6);
+        // Far from both, the other bands are untouched
+        assert!(hsl.at_hue(BAND_HUES[4]).saturation.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_band_changes_only_its_hue() {
+        let mut hsl = Hsl::default();
+        hsl.blue.saturation = -1.0;
+        let blue = oklch(0.5, 0.2, BAND_HUES[5]);
+        let yellow = oklch(0.9, 0.15, BAND_HUES[2]);
+        let [lightness, a, b] = oklab::from_xyz(hsl.adjust(0.0, 0.0, blue));
+        assert!((lightness - 0.5).abs() < 1e-4 && a.hypot(b) < 1e-4);
+        assert_close(hsl.adjust(0.0, 0.0, yellow), yellow);
+    }
+
+    #[test]
+    fn test_vibrance_favours_muted_colors() {
+        let hsl = Hsl::default();
+        let chroma = |xyz| {
+            let [_, a, b] = oklab::from_xyz(xyz);
+            a.hypot(b)
+        };
+        let muted = oklch(0.6, 0.05, 200.0);
+        let vivid = oklch(0.6, 0.25, 200.0);
+        let muted_gain = chroma(hsl.adjust(0.0, 0.5, muted)) / 0.05;
+        let vivid_gain = chroma(hsl.adjust(0.0, 0.5, vivid)) / 0.25;
+        assert!(
+            muted_gain > 1.3 && (vivid_gain - 1.0).abs() < 1e-3,
+            "{muted_gain} {vivid_gain}"
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use crate::color::{self, OutputColorSpace, Transfer};
+
+const HEADER_SIZE: usize = 128;
+/// Samples of tabulated transfer curves, enough to be smooth at 16 bits
+const CURVE_POINTS: usize = 1024;
+
+/// An ICC v2 matrix/TRC display profile that describes how an output color
+/// space is encoded, for embedding in exported files.
+pub fn profile(space: OutputColorSpace) -> Vec<u8> {
+    let matrix = space.rgb_to_pcs();
+    let colorant = |i: usize| xyz_tag([matrix[0][i], matrix[1][i], matrix[2][i]]);
+    let curve = curve_tag(space.transfer());
+    let tags = [
+        (b"desc", description_tag(&space.to_string())),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(color::D50)),
+        (b"rXYZ", colorant(0)),
+        (b"gXYZ", colorant(1)),
+        (b"bXYZ", colorant(2)),
+        (b"rTRC", curve.clone()),
+        (b"gTRC", curve.clone()),
+        (b"bTRC", curve),
+    ];
+
+    let mut table = Vec::new();
+    push_u32(&mut table, tags.len());
+    let mut data = Vec::new();
+    let data_start = HEADER_SIZE + 4 + 12 * tags.len();
+    for (signature, tag) in &tags {
+        table.extend_from_slice(*signature);
+        push_u32(&mut table, data_start + data.len());
+        push_u32(&mut table, tag.len());
+        data.extend_from_slice(tag);
+        // Every tag starts on a four byte boundary
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let size = data_start + data.len();
+    let mut profile = header(size);
+    profile.extend(table);
+    profile.extend(data);
+    profile
+}
+
+fn header(size: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(size);
+    push_u32(&mut header, size);
+    // Preferred CMM
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&[2, 0x10, 0, 0]);
+    header.extend_from_slice(b"mntrRGB XYZ ");
+    // Creation date and time
+    header.extend_from_slice(&[0; 12]);
+    header.extend_from_slice(b"acsp");
+    // Platform, flags, manufacturer, model, attributes and rendering intent
+    header.extend_from_slice(&[0; 28]);
+    push_xyz(&mut header, color::D50);
+    // Creator, profile ID and reserved bytes
+    header.resize(HEADER_SIZE, 0);
+    header
+}
+
+fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+    let mut tag = type_signature(*b"XYZ ");
+    push_xyz(&mut tag, xyz);
+    tag
+}
+
+/// A single gamma value where the curve is a pure power, otherwise a table
+fn curve_tag(transfer: Transfer) -> Vec<u8> {
+    let mut tag = type_signature(*b"curv");
+    match transfer {
+        // An empty curve is the identity
+        Transfer::Linear => push_u32(&mut tag, 0),
+        Transfer::AdobeRgb => {
+            push_u32(&mut tag, 1);
+            // 563/256 in u8Fixed8Number
+            tag.extend_from_slice(&563_u16.to_be_bytes());
+        }
+        Transfer::Srgb | Transfer::Rec709 | Transfer::ProPhoto => {
+            push_u32(&mut tag, CURVE_POINTS);
+            for i in 0..CURVE_POINTS {
+                let linear = transfer.decode(i as f64 / (CURVE_POINTS - 1) as f64);
+                let value = (linear.clamp(0.0, 1.0) * f64::from(u16::MAX)).round() as u16;
+                tag.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+    tag
+}
+
+/// The `textDescriptionType` of ICC v2, with empty Unicode and `ScriptCode`
+/// descriptions
+fn description_tag(text: &str) -> Vec<u8> {
+    let mut tag = type_signature(*b"desc");
+    push_u32(&mut tag, text.len() + 1);
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    // Unicode language code and length
+    tag.extend_from_slice(&[0; 8]);
+    // ScriptCode code, length and its fixed size buffer
+    tag.extend_from_slice(&[0; 3 + 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = type_signature(*b"text");
+    tag.extend_from_slice(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// Every tag type starts with its signature and four reserved bytes
+fn type_signature(signature: [u8; 4]) -> Vec<u8> {
+    let mut tag = signature.to_vec();
+    tag.extend_from_slice(&[0; 4]);
+    tag
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u32).to_be_bytes());
+}
+
+/// As s15Fixed16Number
+fn push_xyz(bytes: &mut Vec<u8>, xyz: [f64; 3]) {
+    for v in xyz {
+        bytes.extend_from_slice(&((v * 65536.0).round() as i32).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::cognitive_complexity)]
+mod tests {
+    use super::*;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> usize {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    /// The tag data of the profile, looked up in the tag table
+    fn tag(profile: &[u8], signature: [u8; 4]) -> &[u8] {
+        let count = read_u32(profile, HEADER_SIZE);
+        let entry = (0..count)
+            .map(|i| HEADER_SIZE + 4 + 12 * i)
+            .find(|&entry| profile[entry..entry + 4] == signature)
+            .unwrap();
+        let offset = read_u32(profile, entry + 4);
+        &profile[offset..offset + read_u32(profile, entry + 8)]
+    }
+
+    #[test]
+    fn test_srgb_profile() {
+        let profile = profile(OutputColorSpace::Srgb);
+        assert_eq!(read_u32(&profile, 0), profile.len());
+        assert_eq!(&profile[36..40], b"acsp");
+
+        // The D50 adapted sRGB red primary
+        let red = tag(&profile, *b"rXYZ");
+        assert_eq!(&red[..4], b"XYZ ");
+        let x = f64::from(read_u32(red, 8) as u32) / 65536.0;
+        assert!((x - 0.4361).abs() < 1e-3, "{x}");
+
+        let curve = tag(&profile, *b"gTRC");
+        assert_eq!(read_u32(curve, 8), CURVE_POINTS);
+        assert_eq!(&curve[curve.len() - 2..], &[0xFF, 0xFF]);
+    }
+}
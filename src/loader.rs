@@ -0,0 +1,474 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        Arc, Condvar, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self as sync_mpsc, RecvTimeoutError},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use derive_more::Display;
+use iced::futures::{
+    Stream, StreamExt,
+    channel::{mpsc, oneshot},
+};
+use tracing::warn;
+
+use crate::{
+    cache,
+    format::{self, Format},
+    program::{Decoded, Program},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum LoadStage {
+    #[display("Reading")]
+    Detecting,
+    #[display("Extracting preview")]
+    Preview,
+    #[display("Decoding")]
+    Decoding,
+}
+
+#[derive(Debug, Clone)]
+pub enum LoadEvent {
+    /// The stage the load is in, and the fraction of the decode that is
+    /// done, estimated from how fast earlier files of the format decoded
+    Progress(PathBuf, LoadStage, f32),
+    /// The embedded JPEG of a raw, to show while the raw decodes
+    Preview(Arc<Decoded>),
+    Done(PathBuf, Result<Arc<Decoded>, String>),
+}
+
+/// A flag that tells a load to stop. The decoders can't be interrupted, so
+/// it is checked between the stages, and a decode that is still waiting for
+/// the decoder thread is skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How often the progress of a decode is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+/// The estimate never quite reaches the end, since it is only an estimate
+const MAX_ESTIMATE: f32 = 0.95;
+
+/// Nanoseconds per KiB of file that the last decode of raws and of other
+/// images took. The starting values only matter for the first file.
+static RAW_RATE: AtomicU64 = AtomicU64::new(20_000);
+static IMAGE_RATE: AtomicU64 = AtomicU64::new(10_000);
+
+const fn decode_rate(format: Format) -> &'static AtomicU64 {
+    match format {
+        Format::Raw | Format::Tiff => &RAW_RATE,
+        Format::Image(_) => &IMAGE_RATE,
+    }
+}
+
+/// The fraction of a decode that is done after `elapsed`, if it runs at
+/// `nanos_per_kib`
+fn estimate(elapsed: Duration, file_size: u64, nanos_per_kib: u64) -> f32 {
+    let expected = Duration::from_nanos(
+        (file_size / 1024)
+            .max(1)
+            .saturating_mul(nanos_per_kib.max(1)),
+    );
+    (elapsed.as_secs_f32() / expected.as_secs_f32()).min(MAX_ESTIMATE)
+}
+
+/// A decode waiting for the decoder thread
+struct DecodeJob {
+    path: PathBuf,
+    format: Format,
+    /// The contents of raws, which are read once for the preview and the
+    /// decode
+    data: Option<Vec<u8>>,
+    cancel: Cancel,
+    result: sync_mpsc::Sender<Result<Decoded, String>>,
+}
+
+/// The thread that runs the decoders of loads, one at a time, so that loads
+/// which are cancelled while their decoder runs don't pile up decoders
+static DECODER: LazyLock<sync_mpsc::Sender<DecodeJob>> = LazyLock::new(|| {
+    let (sender, receiver) = sync_mpsc::channel::<DecodeJob>();
+    std::thread::spawn(move || {
+        for job in receiver {
+            if job.cancel.is_cancelled() {
+                continue;
+            }
+            let result = decode_format(&job.path, job.format, job.data.as_deref())
+                .map_err(|e| e.to_string());
+            let _ = job.result.send(result);
+        }
+    });
+    sender
+});
+
+/// Decodes the image on a background thread, reporting each stage as it
+/// starts and the progress of the decode. Dropping the stream or setting
+/// `cancel` stops the load before its next stage.
+pub fn load(path: PathBuf, cancel: Cancel) -> impl Stream<Item = LoadEvent> {
+    let (sender, receiver) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        let send = |event| sender.unbounded_send(event).is_ok();
+        let result = decode(&path, &cancel, send)
+            .map(Arc::new)
+            .map_err(|e| e.to_string());
+        // Nobody is listening anymore if the load was cancelled
+        let _ = sender.unbounded_send(LoadEvent::Done(path, result));
+    });
+    receiver
+}
+
+/// The files the prefetch worker is asked for, each with the modification
+/// time of the version that is cached already
+#[derive(Debug, Default)]
+struct Wanted {
+    queue: VecDeque<(PathBuf, Option<SystemTime>)>,
+    /// The file being decoded, until a request no longer includes it
+    current: Option<PathBuf>,
+    /// Set once the stream of decoded images is dropped
+    stopped: bool,
+}
+
+/// Decodes images ahead of time, one at a time on a single thread. Each
+/// request replaces the files that are still waiting, and the decode in
+/// progress is dropped once it is no longer wanted.
+#[derive(Debug, Clone, Default)]
+pub struct Prefetcher(Arc<(Mutex<Wanted>, Condvar)>);
+
+impl Prefetcher {
+    /// Starts the worker thread, whose decoded images come out of the
+    /// stream. It stops once the stream is dropped.
+    pub fn start() -> (Self, Prefetched) {
+        let prefetcher = Self::default();
+        let worker = prefetcher.clone();
+        let (sender, receiver) = mpsc::unbounded();
+        std::thread::spawn(move || {
+            while let Some(path) = worker.next() {
+                let result = decode_image(&path).map(Arc::new).map_err(|e| e.to_string());
+                if worker.finish(&path) && sender.unbounded_send((path, result)).is_err() {
+                    return;
+                }
+            }
+        });
+        let decoded = Prefetched {
+            receiver,
+            prefetcher: prefetcher.clone(),
+        };
+        (prefetcher, decoded)
+    }
+
+    /// Replaces the files waiting to be decoded
+    pub fn request(&self, paths: Vec<(PathBuf, Option<SystemTime>)>) {
+        let (wanted, condvar) = &*self.0;
+        let Ok(mut guard) = wanted.lock() else {
+            return;
+        };
+        if guard
+            .current
+            .as_ref()
+            .is_some_and(|current| !paths.iter().any(|(path, _)| path == current))
+        {
+            guard.current = None;
+        }
+        let current = guard.current.clone();
+        guard.queue = paths
+            .into_iter()
+            .filter(|(path, _)| Some(path) != current.as_ref())
+            .collect();
+        condvar.notify_one();
+    }
+
+    /// Waits for the next file that isn't cached in its current version
+    fn next(&self) -> Option<PathBuf> {
+        let (wanted, condvar) = &*self.0;
+        let mut guard = wanted.lock().ok()?;
+        loop {
+            if guard.stopped {
+                return None;
+            }
+            let Some((path, cached)) = guard.queue.pop_front() else {
+                guard = condvar.wait(guard).ok()?;
+                continue;
+            };
+            guard.current = Some(path.clone());
+            drop(guard);
+            // Checking the file can take a while on a network drive
+            let modified = cache::modified(&path);
+            guard = wanted.lock().ok()?;
+            if guard.current.as_ref() == Some(&path) {
+                if modified.is_some() && modified != cached {
+                    return Some(path);
+                }
+                guard.current = None;
+            }
+        }
+    }
+
+    /// Whether the decoded file is still wanted
+    fn finish(&self, path: &Path) -> bool {
+        let Ok(mut guard) = self.0.0.lock() else {
+            return false;
+        };
+        guard.current.take().is_some_and(|current| current == path)
+    }
+
+    /// Wakes the worker up to exit
+    fn stop(&self) {
+        let (wanted, condvar) = &*self.0;
+        if let Ok(mut guard) = wanted.lock() {
+            guard.stopped = true;
+            guard.queue.clear();
+        }
+        condvar.notify_one();
+    }
+}
+
+/// The images the prefetch worker decoded. Dropping it stops the worker.
+#[derive(Debug)]
+pub struct Prefetched {
+    receiver: mpsc::UnboundedReceiver<(PathBuf, Result<Arc<Decoded>, String>)>,
+    prefetcher: Prefetcher,
+}
+
+impl Stream for Prefetched {
+    type Item = (PathBuf, Result<Arc<Decoded>, String>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for Prefetched {
+    fn drop(&mut self) {
+        self.prefetcher.stop();
+    }
+}
+
+/// The images in a folder, as of its modification time
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub modified: Option<SystemTime>,
+    pub files: Vec<PathBuf>,
+}
+
+/// Lists the folder on a background thread, since that can take a while on
+/// a network drive. There is no listing if the folder was last
+/// modified at `listed`, as files were neither added nor removed since.
+pub async fn list_folder(dir: PathBuf, listed: Option<SystemTime>) -> (PathBuf, Option<Listing>) {
+    let (sender, receiver) = oneshot::channel();
+    let thread_dir = dir.clone();
+    std::thread::spawn(move || {
+        // Taken before listing, so that changes during it show up next time
+        let modified = cache::modified(&thread_dir);
+        let listing = (modified.is_none() || modified != listed).then(|| Listing {
+            modified,
+            files: folder_images(&thread_dir),
+        });
+        let _ = sender.send(listing);
+    });
+    let listing = receiver.await.ok().flatten();
+    (dir, listing)
+}
+
+/// Decodes the image on the calling thread, without a preview.
+pub fn decode_image(path: &Path) -> crate::Result<Decoded> {
+    decode_format(path, format::detect(path)?, None)
+}
+
+/// The images in the folder that we know how to decode, sorted by name
+pub fn folder_images(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(std::result::Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| format::is_supported(path))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The folder a file is in, which is the working directory for bare file
+/// names
+pub fn parent_folder(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Decodes any supported image, picking the decoder from the file content.
+/// Raws send their embedded preview first. `send` returns false once
+/// nobody listens anymore, which cancels the load.
+#[allow(clippy::cognitive_complexity)]
+fn decode(
+    path: &Path,
+    cancel: &Cancel,
+    send: impl Fn(LoadEvent) -> bool,
+) -> crate::Result<Decoded> {
+    let send = |event| {
+        if cancel.is_cancelled() || !send(event) {
+            // Also drops the decode if it is still waiting for the decoder
+            cancel.cancel();
+            return false;
+        }
+        true
+    };
+    let progress = |stage, done| send(LoadEvent::Progress(path.to_path_buf(), stage, done));
+    if !progress(LoadStage::Detecting, 0.0) {
+        return Err("Loading was cancelled".into());
+    }
+    let format = format::detect(path)?;
+    let mut data = None;
+    if matches!(format, Format::Raw | Format::Tiff) {
+        let raw = data.insert(std::fs::read(path)?);
+        if !progress(LoadStage::Preview, 0.0) {
+            return Err("Loading was cancelled".into());
+        }
+        match Program::load_preview(path, raw) {
+            Ok(Some(preview)) => {
+                if !send(LoadEvent::Preview(Arc::new(preview))) {
+                    return Err("Loading was cancelled".into());
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not extract a preview from {path:?}: {e}"),
+        }
+    }
+    if !progress(LoadStage::Decoding, 0.0) {
+        return Err("Loading was cancelled".into());
+    }
+
+    // The decoder runs on the decoder thread so that its progress can be
+    // reported, and a cancelled load doesn't wait for it
+    let file_size = match &data {
+        Some(data) => data.len() as u64,
+        None => std::fs::metadata(path)?.len(),
+    };
+    let rate = decode_rate(format);
+    let (sender, receiver) = sync_mpsc::channel();
+    DECODER.send(DecodeJob {
+        path: path.to_path_buf(),
+        format,
+        data,
+        cancel: cancel.clone(),
+        result: sender,
+    })?;
+    let started = Instant::now();
+    let decoded = loop {
+        match receiver.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(result) => break result?,
+            Err(RecvTimeoutError::Timeout) => {
+                let done = estimate(started.elapsed(), file_size, rate.load(Ordering::Relaxed));
+                if !progress(LoadStage::Decoding, done) {
+                    return Err("Loading was cancelled".into());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) if cancel.is_cancelled() => {
+                return Err("Loading was cancelled".into());
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err("The decoder panicked".into()),
+        }
+    };
+    let nanos = u64::try_from(started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+    rate.store(nanos / (file_size / 1024).max(1), Ordering::Relaxed);
+    if !progress(LoadStage::Decoding, 1.0) {
+        return Err("Loading was cancelled".into());
+    }
+    Ok(decoded)
+}
+
+/// Decodes the file with the decoder for its format. Raws are read unless
+/// `data` has their contents already.
+fn decode_format(path: &Path, format: Format, data: Option<&[u8]>) -> crate::Result<Decoded> {
+    let load_raw = || match data {
+        Some(data) => Program::load_cr2_image(path, data),
+        None => Program::load_cr2_image(path, &std::fs::read(path)?),
+    };
+    match format {
+        Format::Raw => load_raw(),
+        Format::Image(_) => Program::load_image(path),
+        // rawloader only accepts TIFFs from cameras it knows
+        Format::Tiff => load_raw().or_else(|_| Program::load_image(path)),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let rate = 1_000_000;
+        let size = 100 * 1024;
+        assert!((estimate(Duration::from_millis(50), size, rate) - 0.5).abs() < 1e-6);
+        // A decode that is slower than the last one doesn't run off the end
+        assert!((estimate(Duration::from_secs(1), size, rate) - MAX_ESTIMATE).abs() < 1e-6);
+        assert!(estimate(Duration::ZERO, 0, 0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_prefetch_request_replaces_waiting_files() {
+        let prefetcher = Prefetcher::default();
+        let paths = |names: &[&str]| names.iter().map(|&n| (PathBuf::from(n), None)).collect();
+        let queued = || {
+            let guard = prefetcher.0.0.lock().unwrap();
+            guard
+                .queue
+                .iter()
+                .map(|(p, _)| p.clone())
+                .collect::<Vec<_>>()
+        };
+        prefetcher.request(paths(&["a", "b"]));
+        prefetcher.0.0.lock().unwrap().current = Some("a".into());
+
+        // The file being decoded isn't queued again while it is wanted
+        prefetcher.request(paths(&["a", "c"]));
+        assert_eq!(queued(), [PathBuf::from("c")]);
+        assert!(prefetcher.finish(Path::new("a")));
+
+        prefetcher.0.0.lock().unwrap().current = Some("c".into());
+        prefetcher.request(paths(&["d"]));
+        assert_eq!(queued(), [PathBuf::from("d")]);
+        assert!(!prefetcher.finish(Path::new("c")));
+    }
+
+    #[test]
+    fn test_cancelled_load_stops() {
+        let cancel = Cancel::default();
+        cancel.cancel();
+        let error = decode(Path::new("missing.cr2"), &cancel, |_| true).unwrap_err();
+        assert_eq!(error.to_string(), "Loading was cancelled");
+    }
+
+    #[test]
+    fn test_nobody_listening_cancels_the_load() {
+        let cancel = Cancel::default();
+        let error = decode(Path::new("missing.cr2"), &cancel, |_| false).unwrap_err();
+        assert_eq!(error.to_string(), "Loading was cancelled");
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn test_dropping_the_prefetch_stream_stops_the_worker() {
+        let (prefetcher, decoded) = Prefetcher::start();
+        drop(decoded);
+        assert!(prefetcher.0.0.lock().unwrap().stopped);
+        assert_eq!(prefetcher.next(), None);
+    }
+}
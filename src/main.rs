@@ -29,29 +29,78 @@
     while_true,
 )]
 
+use std::process::ExitCode;
+
+use clap::Parser;
 use iced::Task;
 
-use crate::ui::{Message, Ui};
+use crate::{
+    cache::ImageCache,
+    ui::{Message, Ui},
+};
 use rawloader as _;
 
+mod batch;
+mod cache;
+mod cli;
+mod color;
 mod compute;
+mod cube;
+mod curve;
+mod curve_editor;
+mod export;
+mod format;
+mod headless;
+mod hsl;
+mod icc;
+mod loader;
+mod metadata;
+mod orientation;
+mod preview;
 mod primitive;
 mod program;
 mod renderer;
+mod settings;
+mod sidecar;
+mod thumbnail;
 mod ui;
 mod uniforms;
 mod util;
+mod white_balance;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-fn main() -> iced::Result {
+#[allow(clippy::cognitive_complexity)]
+fn main() -> ExitCode {
+    let args = cli::Args::parse();
+    args.select_adapter();
     tracing_subscriber::fmt().init();
+    if let Some(cli::Command::Batch(batch)) = &args.command {
+        return match batch::run(batch) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::from(1),
+            Err(e) => {
+                tracing::error!("Batch processing failed: {e}");
+                ExitCode::from(2)
+            }
+        };
+    }
+    if let Err(e) = run_ui(&args) {
+        tracing::error!("{e}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn run_ui(args: &cli::Args) -> iced::Result {
+    let cache_budget = args.cache_budget();
+    let path = args.path.clone();
     iced::application(
-        || {
-            (
-                Ui::default(),
-                Task::done(Message::LoadImage("assets/IMG_7679.jpg".into())),
-            )
+        move || {
+            let open = path
+                .clone()
+                .map_or_else(Task::none, |path| Task::done(Message::Open(path)));
+            (Ui::new(ImageCache::new(cache_budget)), open)
         },
         Ui::update,
         Ui::view,
@@ -59,6 +108,6 @@ fn main() -> iced::Result {
     .subscription(|_| iced::window::events().map(|(_, event)| Message::WindowEvent(event)))
     .antialiasing(true)
     .title("GPU Image")
-    .window_size((1024.0, 1024.0))
+    .window_size((args.width, args.height))
     .run()
 }
@@ -0,0 +1,123 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Seek},
+    path::Path,
+};
+
+use exif::{In, Tag};
+use tracing::debug;
+
+/// A named value for the metadata panel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub value: String,
+}
+
+impl Field {
+    fn new(name: &'static str, value: impl Into<String>) -> Self {
+        Self {
+            name,
+            value: value.into(),
+        }
+    }
+}
+
+/// What we know about how an image was taken and how it is decoded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// What rawloader reports, which decides how the sensor data is handled
+    pub raw: Vec<Field>,
+    pub exif: Vec<Field>,
+}
+
+const EXIF_TAGS: [(&str, Tag); 9] = [
+    ("Make", Tag::Make),
+    ("Model", Tag::Model),
+    ("Lens", Tag::LensModel),
+    ("ISO", Tag::PhotographicSensitivity),
+    ("Shutter", Tag::ExposureTime),
+    ("Aperture", Tag::FNumber),
+    ("Focal length", Tag::FocalLength),
+    ("Taken", Tag::DateTimeOriginal),
+    ("Software", Tag::Software),
+];
+
+impl Metadata {
+    /// Reads the EXIF fields of any image. Files without EXIF data just have
+    /// none to show.
+    pub fn load(path: &Path) -> Self {
+        let exif = File::open(path)
+            .map_err(Into::into)
+            .and_then(|file| read_exif(&mut BufReader::new(file)));
+        Self::from_exif(path, exif)
+    }
+
+    /// Reads the EXIF fields from the contents of the file at `path`, for
+    /// raws that are in memory already
+    pub fn from_bytes(path: &Path, data: &[u8]) -> Self {
+        Self::from_exif(path, read_exif(&mut Cursor::new(data)))
+    }
+
+    #[allow(clippy::cognitive_complexity)]
+    fn from_exif(path: &Path, exif: crate::Result<Vec<Field>>) -> Self {
+        let exif = exif.unwrap_or_else(|e| {
+            debug!("No EXIF data in {path:?}: {e}");
+            Vec::new()
+        });
+        Self {
+            raw: Vec::new(),
+            exif,
+        }
+    }
+
+    pub fn with_raw(mut self, raw: &rawloader::RawImage) -> Self {
+        self.raw = raw_fields(raw);
+        self
+    }
+}
+
+fn read_exif(reader: &mut (impl BufRead + Seek)) -> crate::Result<Vec<Field>> {
+    let exif = exif::Reader::new().read_from_container(reader)?;
+    Ok(EXIF_TAGS
+        .iter()
+        .filter_map(|&(name, tag)| {
+            let field = exif.get_field(tag, In::PRIMARY)?;
+            let value = field.display_value().with_unit(&exif).to_string();
+            Some(Field::new(name, value.trim_matches('"')))
+        })
+        .collect())
+}
+
+fn raw_fields(raw: &rawloader::RawImage) -> Vec<Field> {
+    let [top, right, bottom, left] = raw.crops;
+    let mut fields = vec![
+        Field::new("Make", format!("{} ({})", raw.clean_make, raw.make)),
+        Field::new("Model", format!("{} ({})", raw.clean_model, raw.model)),
+        Field::new("Sensor size", format!("{}x{}", raw.width, raw.height)),
+        Field::new(
+            "Crops",
+            format!("top {top}, right {right}, bottom {bottom}, left {left}"),
+        ),
+        Field::new("CFA", raw.cfa.name.clone()),
+        Field::new("Components", raw.cpp.to_string()),
+        Field::new("White levels", format!("{:?}", raw.whitelevels)),
+        Field::new("Black levels", format!("{:?}", raw.blacklevels)),
+        Field::new("WB coefficients", format_row(&raw.wb_coeffs)),
+    ];
+    // The fourth row is only used by four color sensors
+    let rows = raw
+        .xyz_to_cam
+        .iter()
+        .filter(|row| row.iter().any(|&v| v != 0.0));
+    fields.extend(rows.map(|row| Field::new("XYZ to camera", format_row(row))));
+    fields.push(Field::new("Orientation", format!("{:?}", raw.orientation)));
+    fields
+}
+
+fn format_row(row: &[f32]) -> String {
+    row.iter()
+        .map(|v| format!("{v:.4}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,88 @@
+/// How the stored pixels have to be transformed to show the image upright,
+/// with the same eight cases as the EXIF Orientation tag.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    Transpose,
+    Rotate90,
+    Transverse,
+    Rotate270,
+}
+
+impl Orientation {
+    pub const fn from_exif(value: u16) -> Self {
+        match value {
+            2 => Self::FlipHorizontal,
+            3 => Self::Rotate180,
+            4 => Self::FlipVertical,
+            5 => Self::Transpose,
+            6 => Self::Rotate90,
+            7 => Self::Transverse,
+            8 => Self::Rotate270,
+            _ => Self::Normal,
+        }
+    }
+
+    /// The EXIF tag value, which is also what the shaders switch on
+    pub const fn to_exif(self) -> u32 {
+        match self {
+            Self::Normal => 1,
+            Self::FlipHorizontal => 2,
+            Self::Rotate180 => 3,
+            Self::FlipVertical => 4,
+            Self::Transpose => 5,
+            Self::Rotate90 => 6,
+            Self::Transverse => 7,
+            Self::Rotate270 => 8,
+        }
+    }
+
+    pub const fn swaps_dimensions(self) -> bool {
+        matches!(
+            self,
+            Self::Transpose | Self::Rotate90 | Self::Transverse | Self::Rotate270
+        )
+    }
+
+    /// The displayed size of an image stored with the given size
+    pub const fn apply(self, size: iced::Size<u32>) -> iced::Size<u32> {
+        if self.swaps_dimensions() {
+            iced::Size::new(size.height, size.width)
+        } else {
+            size
+        }
+    }
+
+    /// Maps a position in the displayed image to the stored pixels, both
+    /// normalized to 0..1, like `orient` in the downsample shader
+    pub const fn stored_position(self, position: iced::Point) -> iced::Point {
+        let (x, y) = (position.x, position.y);
+        let (x, y) = match self {
+            Self::Normal => (x, y),
+            Self::FlipHorizontal => (1.0 - x, y),
+            Self::Rotate180 => (1.0 - x, 1.0 - y),
+            Self::FlipVertical => (x, 1.0 - y),
+            Self::Transpose => (y, x),
+            Self::Rotate90 => (y, 1.0 - x),
+            Self::Transverse => (1.0 - y, 1.0 - x),
+            Self::Rotate270 => (1.0 - y, x),
+        };
+        iced::Point::new(x, y)
+    }
+}
+
+impl From<rawloader::Orientation> for Orientation {
+    fn from(orientation: rawloader::Orientation) -> Self {
+        Self::from_exif(orientation.to_u16())
+    }
+}
+
+impl From<image::metadata::Orientation> for Orientation {
+    fn from(orientation: image::metadata::Orientation) -> Self {
+        Self::from_exif(u16::from(orientation.to_exif()))
+    }
+}
@@ -0,0 +1,349 @@
+use std::io::Cursor;
+
+use image::ImageDecoder;
+
+use crate::orientation::Orientation;
+
+/// Fuji RAF stores the offset and length of its JPEG at fixed positions
+const RAF_SIGNATURE: &[u8] = b"FUJIFILMCCD-RAW";
+const RAF_JPEG_OFFSET: usize = 84;
+const RAF_JPEG_LENGTH: usize = 88;
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 0xFE;
+const TAG_COMPRESSION: u16 = 0x103;
+const TAG_STRIP_OFFSETS: u16 = 0x111;
+const TAG_ORIENTATION: u16 = 0x112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x117;
+const TAG_SUB_IFDS: u16 = 0x14A;
+const TAG_JPEG_OFFSET: u16 = 0x201;
+const TAG_JPEG_LENGTH: u16 = 0x202;
+/// Panasonic RW2 keeps its preview in a tag of its own
+const TAG_JPG_FROM_RAW: u16 = 0x2E;
+const TAG_WHITE_LEVEL: u16 = 0xC61D;
+
+const COMPRESSION_OLD_JPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+
+/// Guards against IFD chains that loop back on themselves
+const MAX_IFDS: usize = 16;
+
+/// The preview JPEG embedded in a camera raw, along with the orientation
+/// the camera recorded for it.
+#[derive(Debug)]
+pub struct Preview {
+    pub image: image::DynamicImage,
+    pub orientation: Orientation,
+}
+
+/// Extracts the largest embedded JPEG from the contents of a raw file.
+/// Returns `None` if the file doesn't have one that the image crate can
+/// decode.
+pub fn extract(data: &[u8]) -> Option<Preview> {
+    let (mut candidates, orientation) = if data.starts_with(RAF_SIGNATURE) {
+        let offset = read_u32(data, RAF_JPEG_OFFSET, true)? as usize;
+        let len = read_u32(data, RAF_JPEG_LENGTH, true)? as usize;
+        (vec![(offset, len)], None)
+    } else {
+        let tiff = Tiff::new(data)?;
+        let mut candidates = Vec::new();
+        let first = tiff.u32(4)? as usize;
+        tiff.collect_jpegs(first, &mut candidates, &mut 0);
+        let orientation = tiff
+            .ifd(first)
+            .and_then(|(entries, _)| entries.into_iter().find(|e| e.tag == TAG_ORIENTATION))
+            .and_then(|entry| tiff.value(&entry))
+            .and_then(|value| u16::try_from(value).ok())
+            .map(Orientation::from_exif);
+        (candidates, orientation)
+    };
+    // Canon and Nikon store lossless JPEG raw data under the same tags,
+    // which the image crate can't decode, so try the largest one first and
+    // fall back to the smaller ones.
+    candidates.sort_by_key(|&(_, len)| std::cmp::Reverse(len));
+    candidates.into_iter().find_map(|(offset, len)| {
+        let jpeg = data.get(offset..offset.checked_add(len)?)?;
+        if !jpeg.starts_with(&[0xFF, 0xD8]) {
+            return None;
+        }
+        let mut decoder = image::codecs::jpeg::JpegDecoder::new(Cursor::new(jpeg)).ok()?;
+        let jpeg_orientation = decoder.orientation().ok().map(Orientation::from);
+        let image = image::DynamicImage::from_decoder(decoder).ok()?;
+        Some(Preview {
+            image,
+            orientation: orientation.or(jpeg_orientation).unwrap_or_default(),
+        })
+    })
+}
+
+/// The `WhiteLevel` of the full resolution image in a DNG, if it has one.
+///
+/// rawloader fills in an integer default for a missing tag, while the DNG
+/// spec defaults it to 1.0 for floating point samples.
+pub fn dng_white_level(data: &[u8]) -> Option<u32> {
+    let tiff = Tiff::new(data)?;
+    let entries = tiff.main_image(tiff.u32(4)? as usize, &mut 0)?;
+    let entry = entries.iter().find(|e| e.tag == TAG_WHITE_LEVEL)?;
+    tiff.value(entry)
+}
+
+fn read_u16(data: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Just enough of a TIFF reader to find the embedded JPEGs and the white
+/// level of DNGs. ORF and RW2 use a different magic number but the same
+/// layout.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// Position of the value, or of the offset to it if it doesn't fit
+    value_pos: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match data.get(..2)? {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        Some(Self { data, big_endian })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        read_u16(self.data, offset, self.big_endian)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        read_u32(self.data, offset, self.big_endian)
+    }
+
+    /// The entries of the IFD at `offset` and the offset of the next one
+    fn ifd(&self, offset: usize) -> Option<(Vec<Entry>, usize)> {
+        let count = self.u16(offset)? as usize;
+        let entries = (0..count)
+            .map(|i| {
+                let pos = offset + 2 + i * 12;
+                Some(Entry {
+                    tag: self.u16(pos)?,
+                    kind: self.u16(pos + 2)?,
+                    count: self.u32(pos + 4)?,
+                    value_pos: pos + 8,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let next = self.u32(offset + 2 + count * 12)? as usize;
+        Some((entries, next))
+    }
+
+    /// The values of a SHORT, LONG or IFD entry
+    fn values(&self, entry: &Entry) -> Option<Vec<u32>> {
+        let size = match entry.kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return None,
+        };
+        let count = entry.count as usize;
+        let start = if size * count > 4 {
+            self.u32(entry.value_pos)? as usize
+        } else {
+            entry.value_pos
+        };
+        (0..count)
+            .map(|i| {
+                let pos = start + i * size;
+                if size == 2 {
+                    self.u16(pos).map(u32::from)
+                } else {
+                    self.u32(pos)
+                }
+            })
+            .collect()
+    }
+
+    fn value(&self, entry: &Entry) -> Option<u32> {
+        self.values(entry)?.first().copied()
+    }
+
+    /// Walks the IFD chain starting at `offset`, including `SubIFDs`, and
+    /// collects the offset and length of everything that may be a JPEG.
+    #[allow(clippy::cognitive_complexity)]
+    fn collect_jpegs(
+        &self,
+        mut offset: usize,
+        candidates: &mut Vec<(usize, usize)>,
+        visited: &mut usize,
+    ) {
+        while offset != 0 && *visited < MAX_IFDS {
+            *visited += 1;
+            let Some((entries, next)) = self.ifd(offset) else {
+                return;
+            };
+            let find = |tag| entries.iter().find(|e| e.tag == tag);
+            let value = |tag| find(tag).and_then(|e| self.value(e));
+
+            if let (Some(start), Some(len)) = (value(TAG_JPEG_OFFSET), value(TAG_JPEG_LENGTH)) {
+                candidates.push((start as usize, len as usize));
+            }
+            if matches!(
+                value(TAG_COMPRESSION),
+                Some(COMPRESSION_OLD_JPEG | COMPRESSION_JPEG)
+            ) {
+                let strips = find(TAG_STRIP_OFFSETS).and_then(|e| self.values(e));
+                let counts = find(TAG_STRIP_BYTE_COUNTS).and_then(|e| self.values(e));
+                // A JPEG split into several strips isn't a file of its own
+                if let (Some([start]), Some([len])) = (strips.as_deref(), counts.as_deref()) {
+                    candidates.push((*start as usize, *len as usize));
+                }
+            }
+            if let Some(entry) = find(TAG_JPG_FROM_RAW)
+                && let Some(start) = self.u32(entry.value_pos)
+            {
+                candidates.push((start as usize, entry.count as usize));
+            }
+            if let Some(sub_ifds) = find(TAG_SUB_IFDS).and_then(|e| self.values(e)) {
+                for sub_ifd in sub_ifds {
+                    self.collect_jpegs(sub_ifd as usize, candidates, visited);
+                }
+            }
+            offset = next;
+        }
+    }
+
+    /// The entries of the first IFD, in the chain starting at `offset` or
+    /// below it, that holds the full resolution image rather than a
+    /// thumbnail
+    fn main_image(&self, mut offset: usize, visited: &mut usize) -> Option<Vec<Entry>> {
+        while offset != 0 && *visited < MAX_IFDS {
+            *visited += 1;
+            let (entries, next) = self.ifd(offset)?;
+            let find = |tag| entries.iter().find(|e| e.tag == tag);
+            let subfile_type = find(TAG_NEW_SUBFILE_TYPE).and_then(|e| self.value(e));
+            let sub_ifds = find(TAG_SUB_IFDS).and_then(|e| self.values(e));
+            if subfile_type.unwrap_or(0) == 0 {
+                return Some(entries);
+            }
+            for sub_ifd in sub_ifds.unwrap_or_default() {
+                if let Some(found) = self.main_image(sub_ifd as usize, visited) {
+                    return Some(found);
+                }
+            }
+            offset = next;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn encode_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = image::DynamicImage::new_rgb8(width, height);
+        let mut jpeg = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        jpeg
+    }
+
+    fn to_u32(value: usize) -> u32 {
+        u32::try_from(value).unwrap()
+    }
+
+    fn entry(tag: u16, kind: u16, value: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(tag.to_le_bytes());
+        bytes.extend(kind.to_le_bytes());
+        bytes.extend(1_u32.to_le_bytes());
+        bytes.extend(value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_extract_largest_tiff_preview() {
+        let small = encode_jpeg(4, 2);
+        let large = encode_jpeg(64, 32);
+        // Header, then IFD0 with three entries and a second IFD with three
+        let ifd1 = 8 + 2 + 3 * 12 + 4;
+        let small_offset = ifd1 + 2 + 3 * 12 + 4;
+        let large_offset = small_offset + small.len();
+
+        let mut data = b"II*\0".to_vec();
+        data.extend(8_u32.to_le_bytes());
+        data.extend(3_u16.to_le_bytes());
+        data.extend(entry(TAG_ORIENTATION, 3, 6));
+        data.extend(entry(TAG_JPEG_OFFSET, 4, to_u32(small_offset)));
+        data.extend(entry(TAG_JPEG_LENGTH, 4, to_u32(small.len())));
+        data.extend(to_u32(ifd1).to_le_bytes());
+        data.extend(3_u16.to_le_bytes());
+        data.extend(entry(TAG_COMPRESSION, 3, COMPRESSION_OLD_JPEG));
+        data.extend(entry(TAG_STRIP_OFFSETS, 4, to_u32(large_offset)));
+        data.extend(entry(TAG_STRIP_BYTE_COUNTS, 4, to_u32(large.len())));
+        data.extend(0_u32.to_le_bytes());
+        data.extend(&small);
+        data.extend(&large);
+
+        let preview = extract(&data).unwrap();
+        assert_eq!((preview.image.width(), preview.image.height()), (64, 32));
+        assert_eq!(preview.orientation, Orientation::Rotate90);
+    }
+
+    #[test]
+    fn test_extract_raf_preview() {
+        let jpeg = encode_jpeg(8, 4);
+        let mut data = RAF_SIGNATURE.to_vec();
+        data.resize(100, 0);
+        data[RAF_JPEG_OFFSET..RAF_JPEG_OFFSET + 4].copy_from_slice(&100_u32.to_be_bytes());
+        data[RAF_JPEG_LENGTH..RAF_JPEG_LENGTH + 4]
+            .copy_from_slice(&to_u32(jpeg.len()).to_be_bytes());
+        data.extend(&jpeg);
+
+        let preview = extract(&data).unwrap();
+        assert_eq!((preview.image.width(), preview.image.height()), (8, 4));
+        assert!(extract(b"II*\0\x08\0\0\0\0\0\0\0\0\0").is_none());
+    }
+
+    #[test]
+    fn test_dng_white_level() {
+        // A thumbnail in IFD0 and the raw in its SubIFD
+        let sub_ifd = 8 + 2 + 3 * 12 + 4;
+        let mut data = b"II*\0".to_vec();
+        data.extend(8_u32.to_le_bytes());
+        data.extend(3_u16.to_le_bytes());
+        data.extend(entry(TAG_NEW_SUBFILE_TYPE, 4, 1));
+        data.extend(entry(TAG_SUB_IFDS, 13, to_u32(sub_ifd)));
+        data.extend(entry(TAG_WHITE_LEVEL, 3, 255));
+        data.extend(0_u32.to_le_bytes());
+        data.extend(2_u16.to_le_bytes());
+        data.extend(entry(TAG_NEW_SUBFILE_TYPE, 4, 0));
+        data.extend(entry(TAG_WHITE_LEVEL, 4, 65_535));
+        data.extend(0_u32.to_le_bytes());
+        assert_eq!(dng_white_level(&data), Some(65_535));
+
+        // Without the tag the raw has no white level of its own
+        data[sub_ifd + 2 + 12..sub_ifd + 4 + 12].copy_from_slice(&0xFFFE_u16.to_le_bytes());
+        assert_eq!(dng_white_level(&data), None);
+    }
+}
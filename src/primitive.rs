@@ -3,23 +3,38 @@ use std::{
     sync::Arc,
 };
 
+use image::ImageDecoder;
+use tracing::error;
+
 use crate::{
     Result,
     compute::{
-        self, demosaic::DemosaicShader, downsample::DownsampleShader, fragment::FragmentShader,
-        processing::ProcessingShader,
+        self, FloatScale,
+        demosaic::{DemosaicAlgorithm, DemosaicShader},
+        downsample::DownsampleShader,
+        fragment::FragmentShader,
+        hsl::HslShader,
+        processing::{self, ProcessingShader},
     },
+    cube,
+    curve::Curves,
+    orientation::Orientation,
     program,
     renderer::{ComputeRenderer, Textures},
     uniforms::{self, Uniforms},
     util::{Resize, Tof32, Tou32, timed},
 };
 
+pub const XTRANS_CFA_SIZE: u32 = 6;
+
 #[derive(Debug)]
 pub struct Primitive {
     pub uniforms: Uniforms,
     pub image_path: PathBuf,
     pub image: Arc<program::Image>,
+    pub demosaic_algorithm: DemosaicAlgorithm,
+    pub curves: Curves,
+    pub lut: Option<Arc<cube::Lut>>,
 }
 
 impl Primitive {
@@ -29,7 +44,15 @@ impl Primitive {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
     ) {
-        if renderer.image_path != self.image_path
+        let algorithm_changed = renderer.demosaic_algorithm != self.demosaic_algorithm;
+        if algorithm_changed {
+            timed("Switching demosaic algorithm", || {
+                renderer.set_demosaic_algorithm(device, self.demosaic_algorithm);
+            });
+        }
+        // A raw replaces its preview under the same path, so compare the
+        // images themselves
+        if !std::ptr::eq(renderer.image.as_ptr(), Arc::as_ptr(&self.image))
             || should_resize(
                 self.uniforms.window_size.to_u32(),
                 renderer.textures.output_size,
@@ -39,6 +62,10 @@ impl Primitive {
                 self.recreate_buffers(renderer, device, queue);
                 self.run_demosaic(device, queue, renderer);
             });
+        } else if algorithm_changed {
+            timed("Running demosaic", || {
+                self.run_demosaic(device, queue, renderer);
+            });
         }
     }
 
@@ -47,7 +74,12 @@ impl Primitive {
             label: Some("primitive.recreate_buffers.encoder"),
         });
         renderer.copy_uniforms_to_device(queue, &self.uniforms);
-        compute::enqueue_workload(&mut encoder, &renderer.demosaic_shader);
+        let shader = if self.uniforms.cfa_size == XTRANS_CFA_SIZE {
+            &renderer.xtrans_shader
+        } else {
+            &renderer.demosaic_shader
+        };
+        compute::enqueue_workload(&mut encoder, shader);
         queue.submit(Some(encoder.finish()));
     }
 
@@ -60,11 +92,14 @@ impl Primitive {
         let image = self.image.as_ref();
         // TODO: No need to recreate the full size texture if the image hasn't changed
         let textures = self.create_image_textures(image, device, queue);
-        renderer.image_path.clone_from(&self.image_path);
+        renderer.image = Arc::downgrade(&self.image);
         renderer.textures = textures;
+        renderer.curves = None;
+        renderer.lut = None;
         renderer.replace_bind_groups(device);
     }
 
+    #[allow(clippy::cognitive_complexity)]
     fn create_image_textures(
         &self,
         image: &program::Image,
@@ -73,29 +108,13 @@ impl Primitive {
     ) -> Textures {
         let image_size = iced::Size::new(image.width(), image.height());
         let window_size = self.uniforms.window_size.to_u32();
-        let full_texture = match image {
-            program::Image::DynamicImage(dynamic_image) => {
-                compute::create_texture(device, dynamic_image)
-            }
-            program::Image::RawImage(_) => {
-                compute::create_float_texture(device, image_size, wgpu::TextureFormat::R32Float)
-            }
-        };
-        let full_output_texture =
-            compute::create_float_texture(device, image_size, wgpu::TextureFormat::Rgba32Float);
-        let input_texture = compute::create_window_texture(device, window_size, image_size);
-        let output_texture = compute::create_window_texture(device, window_size, image_size);
-        let output_size = crate::util::calculate_image_size(window_size, image_size).resize(1.2);
-        compute::write_texture(queue, &full_texture, image);
-
-        Textures {
-            full_texture,
-            full_output_texture,
-            input_texture,
-            output_texture,
-            image_size,
-            output_size,
+        let display_size = self.uniforms.orientation.apply(image_size);
+        let output_size = crate::util::calculate_image_size(window_size, display_size).resize(1.2);
+        let textures = Textures::new(device, image, output_size);
+        if let Err(e) = compute::write_texture(queue, &textures.full_texture, image) {
+            error!("Error uploading {:?}: {e}", self.image_path);
         }
+        textures
     }
 }
 
@@ -113,18 +132,28 @@ impl iced::widget::shader::Primitive for Primitive {
         let textures = self.create_image_textures(image, device, queue);
         let fragment_shader =
             FragmentShader::compile(device, format, &uniforms, &textures.output_texture);
-        let demosaic_shader = DemosaicShader::compile(device, &uniforms, &textures);
+        let demosaic_shader =
+            DemosaicShader::compile(device, &uniforms, &textures, self.demosaic_algorithm);
+        let xtrans_shader = DemosaicShader::compile_xtrans(device, &uniforms, &textures);
         let downsample_shader = DownsampleShader::compile(device, &uniforms, &textures);
         let processing_shader = ProcessingShader::compile(device, &uniforms, &textures);
+        let hsl_shader = HslShader::compile(device, &uniforms, &textures);
+        let encode_shader = ProcessingShader::compile_encode(device, &uniforms, &textures);
 
         let renderer = ComputeRenderer {
             fragment_shader,
             uniforms,
             demosaic_shader,
+            demosaic_algorithm: self.demosaic_algorithm,
+            xtrans_shader,
             downsample_shader,
             processing_shader,
-            image_path: self.image_path.clone(),
+            hsl_shader,
+            encode_shader,
+            image: Arc::downgrade(&self.image),
             textures,
+            curves: None,
+            lut: None,
         };
         self.run_demosaic(device, queue, &renderer);
         renderer
@@ -139,6 +168,21 @@ impl iced::widget::shader::Primitive for Primitive {
         _viewport: &iced::widget::shader::Viewport,
     ) {
         self.check_resize(renderer, device, queue);
+        if renderer.curves.as_ref() != Some(&self.curves) {
+            processing::write_curves(queue, &renderer.textures.curve_lut, &self.curves);
+            renderer.curves = Some(self.curves.clone());
+        }
+        if let Some(lut) = &self.lut
+            && renderer
+                .lut
+                .as_ref()
+                .is_none_or(|current| !Arc::ptr_eq(current, lut))
+        {
+            renderer.textures.lut = processing::create_lut_texture(device, lut.size as u32);
+            processing::write_lut(queue, &renderer.textures.lut, lut);
+            renderer.replace_bind_groups(device);
+            renderer.lut = Some(lut.clone());
+        }
         queue.write_buffer(
             &renderer.uniforms,
             0,
@@ -156,6 +200,8 @@ impl iced::widget::shader::Primitive for Primitive {
         // compute::enqueue_workload(encoder, &renderer.demosaic_shader);
         compute::enqueue_workload(encoder, &renderer.downsample_shader);
         compute::enqueue_workload(encoder, &renderer.processing_shader);
+        compute::enqueue_workload(encoder, &renderer.hsl_shader);
+        compute::enqueue_workload(encoder, &renderer.encode_shader);
         enqueue_draw(renderer, encoder, target, bounds);
     }
 }
@@ -211,16 +257,31 @@ fn should_resize(new_size: iced::Size<u32>, current_size: iced::Size<u32>) -> bo
 }
 
 pub fn load_image(path: &Path) -> Result<image::DynamicImage> {
-    let image = image::ImageReader::open(path)?.decode()?;
+    let image = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?;
     Ok(image)
 }
 
-pub fn load_cr2_image(path: &Path) -> Result<rawloader::RawImage> {
-    let image = rawloader::decode_file(path)?;
+/// Reads the EXIF orientation, without decoding the pixels
+pub fn load_orientation(path: &Path) -> Result<Orientation> {
+    let mut decoder = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    Ok(decoder.orientation()?.into())
+}
+
+/// Decodes a raw from the contents of its file
+pub fn load_cr2_image(data: &[u8]) -> Result<rawloader::RawImage> {
+    let mut image = rawloader::decode(&mut std::io::Cursor::new(data))?;
+    if matches!(image.data, rawloader::RawImageData::Float(_)) {
+        let white_level = crate::preview::dng_white_level(data);
+        compute::float_raw_to_sensor_units(&mut image, FloatScale::from_white_level(white_level));
+    }
     Ok(image)
 }
 
-fn create_uniforms_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+pub fn create_uniforms_buffer(device: &wgpu::Device) -> wgpu::Buffer {
     device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("halo.pipeline.uniforms"),
         size: std::mem::size_of::<uniforms::Raw>() as wgpu::BufferAddress,
@@ -5,8 +5,25 @@ use std::{
 };
 
 use derive_more::From;
+use tracing::warn;
 
-use crate::{primitive::Primitive, ui::Message, uniforms::Uniforms, util::Tof32};
+use crate::{
+    color::{self, OutputColorSpace},
+    compute, cube, loader,
+    metadata::Metadata,
+    orientation::Orientation,
+    primitive::Primitive,
+    settings::Settings,
+    sidecar::Sidecar,
+    ui::Message,
+    uniforms::{self, InputKind, Uniforms},
+    util::Tof32,
+    white_balance::{self, WhiteBalance},
+};
+
+/// Half the size of the square the white balance is picked from, in sensor
+/// pixels
+const PICK_RADIUS: usize = 4;
 
 #[derive(Debug, Clone)]
 pub struct Program {
@@ -14,31 +31,43 @@ pub struct Program {
     pub image: Arc<Image>,
     pub mouse_pos: (f32, f32),
     pub scroll_delta: f32,
+    /// Size of the image as displayed, after applying the orientation
     pub image_size: iced::Size<u32>,
+    pub orientation: Orientation,
     pub last_iteration: Instant,
     pub last_frame_time: Duration,
 
-    pub exposure: f32,
-    pub contrast: f32,
+    pub settings: Settings,
+    /// The LUT of `settings.lut`, with the path it was loaded from
+    lut: Option<(PathBuf, Arc<cube::Lut>)>,
 }
 
 #[derive(Debug, From)]
+#[allow(clippy::enum_variant_names)]
 pub enum Image {
+    /// Display ready pixels, encoded as sRGB
     DynamicImage(image::DynamicImage),
+    /// Scene-linear float pixels, like those of EXR files. Values above 1
+    /// are highlights rather than clipped.
+    #[from(skip)]
+    LinearImage(image::DynamicImage),
     RawImage(Box<rawloader::RawImage>),
+    /// The JPEG embedded in a raw, shown until the raw itself is decoded
+    #[from(skip)]
+    Preview(image::DynamicImage),
 }
 
 impl Image {
     pub fn width(&self) -> u32 {
         match self {
-            Self::DynamicImage(img) => img.width(),
+            Self::DynamicImage(img) | Self::LinearImage(img) | Self::Preview(img) => img.width(),
             Self::RawImage(img) => img.width as u32,
         }
     }
 
     pub fn height(&self) -> u32 {
         match self {
-            Self::DynamicImage(img) => img.height(),
+            Self::DynamicImage(img) | Self::LinearImage(img) | Self::Preview(img) => img.height(),
             Self::RawImage(img) => img.height as u32,
         }
     }
@@ -46,6 +75,85 @@ impl Image {
     pub fn dimensions(&self) -> (u32, u32) {
         (self.width(), self.height())
     }
+
+    pub const fn is_preview(&self) -> bool {
+        matches!(self, Self::Preview(_))
+    }
+
+    /// Bytes taken up by the pixels
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Self::DynamicImage(img) | Self::LinearImage(img) | Self::Preview(img) => {
+                img.as_bytes().len()
+            }
+            Self::RawImage(raw) => match &raw.data {
+                rawloader::RawImageData::Integer(items) => items.len() * size_of::<u16>(),
+                rawloader::RawImageData::Float(items) => items.len() * size_of::<f32>(),
+            },
+        }
+    }
+
+    /// Checks that the image can be uploaded, so that a broken one fails
+    /// to load rather than to show. The pixels stay in their decoded format,
+    /// which takes a fraction of the memory in the cache, and are converted
+    /// to the texel format of the full size texture as they are uploaded.
+    pub fn prepare(self) -> crate::Result<Self> {
+        match self {
+            // Float formats hold linear values, everything else is encoded
+            Self::DynamicImage(
+                img @ (image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_)),
+            ) => Ok(Self::LinearImage(img)),
+            Self::RawImage(raw) => {
+                if cfa_pattern(&raw.cfa).is_none() {
+                    return Err(format!(
+                        "Unsupported {}x{} CFA pattern {:?}",
+                        raw.cfa.width, raw.cfa.height, raw.cfa.name
+                    )
+                    .into());
+                }
+                compute::check_raw_data(&raw)?;
+                Ok(Self::RawImage(raw))
+            }
+            image => Ok(image),
+        }
+    }
+}
+
+/// A decoded image along with what's needed to display it.
+#[derive(Debug, Clone)]
+pub struct Decoded {
+    pub path: PathBuf,
+    pub image: Arc<Image>,
+    pub orientation: Orientation,
+    /// The saved edits, as of when the image was loaded
+    pub sidecar: Option<Sidecar>,
+    pub metadata: Metadata,
+}
+
+impl Decoded {
+    /// The settings from the sidecar, or the defaults for an unedited image
+    #[allow(clippy::cognitive_complexity)]
+    pub fn settings(&self) -> Settings {
+        let Some(sidecar) = &self.sidecar else {
+            return Settings::default();
+        };
+        sidecar.settings().unwrap_or_else(|e| {
+            warn!(
+                "Ignoring the settings in the sidecar of {:?}: {e}",
+                self.path
+            );
+            Settings::default()
+        })
+    }
+}
+
+/// A broken sidecar shouldn't keep the image from loading
+#[allow(clippy::cognitive_complexity)]
+fn load_sidecar(path: &Path) -> Option<Sidecar> {
+    Sidecar::load(path).unwrap_or_else(|e| {
+        warn!("Could not read the sidecar of {path:?}: {e}");
+        None
+    })
 }
 
 impl Default for Program {
@@ -56,89 +164,317 @@ impl Default for Program {
             mouse_pos: (-1.0, -1.0),
             scroll_delta: 0.0,
             image_size: iced::Size::new(0, 0),
+            orientation: Orientation::default(),
             last_iteration: Instant::now(),
             last_frame_time: Duration::default(),
-            exposure: 0.0,
-            contrast: 1.0,
+            settings: Settings::default(),
+            lut: None,
         }
     }
 }
 
 impl Program {
-    pub fn load_image(&mut self, path: &Path) -> crate::Result<()> {
-        self.image_path = path.to_path_buf();
+    pub fn load_image(path: &Path) -> crate::Result<Decoded> {
         let image = crate::primitive::load_image(path)?;
-        self.image_size = iced::Size::new(image.width(), image.height());
-        self.image = Arc::new(image.into());
+        Ok(Decoded {
+            path: path.to_path_buf(),
+            orientation: crate::primitive::load_orientation(path)?,
+            image: Arc::new(Image::from(image).prepare()?),
+            sidecar: load_sidecar(path),
+            metadata: Metadata::load(path),
+        })
+    }
+
+    /// Extracts the JPEG preview embedded in a raw, if it has one. `data` is
+    /// the contents of the file at `path`.
+    pub fn load_preview(path: &Path, data: &[u8]) -> crate::Result<Option<Decoded>> {
+        let Some(preview) = crate::preview::extract(data) else {
+            return Ok(None);
+        };
+        Ok(Some(Decoded {
+            path: path.to_path_buf(),
+            orientation: preview.orientation,
+            image: Arc::new(Image::Preview(preview.image).prepare()?),
+            sidecar: load_sidecar(path),
+            metadata: Metadata::from_bytes(path, data),
+        }))
+    }
+
+    /// Decodes a raw. `data` is the contents of the file at `path`.
+    pub fn load_cr2_image(path: &Path, data: &[u8]) -> crate::Result<Decoded> {
+        let image = crate::primitive::load_cr2_image(data)?;
+        Ok(Decoded {
+            path: path.to_path_buf(),
+            orientation: image.orientation.into(),
+            metadata: Metadata::from_bytes(path, data).with_raw(&image),
+            image: Arc::new(Image::from(Box::new(image)).prepare()?),
+            sidecar: load_sidecar(path),
+        })
+    }
+
+    #[allow(clippy::cognitive_complexity)]
+    pub fn set_image(&mut self, decoded: &Decoded) {
+        let (width, height) = decoded.image.dimensions();
+        self.image_path.clone_from(&decoded.path);
+        self.orientation = decoded.orientation;
+        self.image_size = self.orientation.apply(iced::Size::new(width, height));
+        self.image = decoded.image.clone();
+        self.settings = decoded.settings();
+        if let Err(e) = self.load_lut() {
+            warn!("Could not load the LUT of {:?}: {e}", decoded.path);
+        }
+    }
+
+    /// Loads the LUT named in the settings, unless it is loaded already
+    pub fn load_lut(&mut self) -> crate::Result<()> {
+        let Some(path) = self.settings.lut.as_deref().map(|lut| self.lut_path(lut)) else {
+            self.lut = None;
+            return Ok(());
+        };
+        if self.lut.as_ref().is_some_and(|(loaded, _)| *loaded == path) {
+            return Ok(());
+        }
+        self.lut = None;
+        let lut = cube::Lut::load(&path)?;
+        self.lut = Some((path, Arc::new(lut)));
         Ok(())
     }
 
-    pub fn load_cr2_image(&mut self, path: &Path) -> crate::Result<()> {
-        self.image_path = path.to_path_buf();
-        let image = crate::primitive::load_cr2_image(path)?;
-        self.image_size = iced::Size::new(image.width as u32, image.height as u32);
-        self.image = Arc::new(Box::new(image).into());
+    /// Switches to another LUT, keeping the current one if it can't be
+    /// loaded
+    pub fn set_lut(&mut self, path: Option<PathBuf>) -> crate::Result<()> {
+        let resolved = path.as_deref().map(|lut| self.lut_path(lut));
+        let lut = resolved.as_deref().map(cube::Lut::load).transpose()?;
+        self.lut = resolved.zip(lut.map(Arc::new));
+        self.settings.lut = path;
         Ok(())
     }
-}
 
-impl iced::widget::shader::Program<Message> for Program {
-    type State = ();
+    /// Where a LUT named in the settings is. A relative path is relative to
+    /// the folder of the image, which is where its sidecar is.
+    fn lut_path(&self, lut: &Path) -> PathBuf {
+        loader::parent_folder(&self.image_path).join(lut)
+    }
 
-    type Primitive = Primitive;
+    pub fn lut(&self) -> Option<&cube::Lut> {
+        self.lut.as_ref().map(|(_, lut)| &**lut)
+    }
 
-    fn draw(
-        &self,
-        _state: &Self::State,
-        _cursor: iced::mouse::Cursor,
-        bounds: iced::Rectangle,
-    ) -> Self::Primitive {
-        let image_size = self.image_size.to_f32();
-        let (cam_2_xyz, xyz_2_srgb, whitelevels, blacklevels, crops) = match &*self.image {
-            Image::DynamicImage(_) => (
-                [
-                    [1.0, 0.0, 0.0, 0.0],
-                    [0.0, 1.0, 0.0, 0.0],
-                    [0.0, 0.0, 1.0, 0.0],
-                ],
-                [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    /// Size of the full resolution output, after cropping the sensor
+    /// borders and applying the orientation
+    pub fn export_size(&self) -> iced::Size<u32> {
+        let (width, height) = self.image.dimensions();
+        let [top, right, bottom, left] = match &*self.image {
+            Image::RawImage(raw) => to_u32(raw.crops),
+            Image::DynamicImage(_) | Image::LinearImage(_) | Image::Preview(_) => [0; 4],
+        };
+        self.orientation.apply(iced::Size::new(
+            width.saturating_sub(left + right),
+            height.saturating_sub(top + bottom),
+        ))
+    }
+
+    /// The uniforms for processing the image shown at `window_size`
+    pub fn uniforms(&self, window_size: iced::Size<f32>) -> Uniforms {
+        let (width, height) = self.image.dimensions();
+        let image_size = iced::Size::new(width, height).to_f32();
+        let settings = &self.settings;
+        let (
+            input_kind,
+            cam_2_xyz,
+            whitelevels,
+            blacklevels,
+            crops,
+            (cfa, cfa_size),
+            wb_multipliers,
+        ) = match &*self.image {
+            Image::DynamicImage(_) | Image::Preview(_) => (
+                InputKind::Srgb,
+                pad_rows(OutputColorSpace::Srgb.rgb_to_xyz()),
+                [1.0; 4],
+                [0.0; 4],
+                [0; 4],
+                (uniforms::tile_cfa(RGGB), 2),
                 [1.0; 4],
+            ),
+            // Unlike encoded values, linear ones have no white to clip at
+            Image::LinearImage(_) => (
+                InputKind::Linear,
+                pad_rows(OutputColorSpace::Srgb.rgb_to_xyz()),
+                [f32::MAX; 4],
                 [0.0; 4],
                 [0; 4],
+                (uniforms::tile_cfa(RGGB), 2),
+                [1.0; 4],
             ),
             Image::RawImage(raw) => (
+                InputKind::Raw,
                 raw.cam_to_xyz(),
-                [
-                    [3.2406, -0.9689, 0.0557],
-                    [-1.5372, 1.8758, -0.2040],
-                    [-0.4986, 0.0415, 1.0570],
-                ],
                 to_float(raw.whitelevels),
                 to_float(raw.blacklevels),
                 to_u32(raw.crops),
+                // `prepare` rejects the patterns without a layout
+                cfa_pattern(&raw.cfa).unwrap_or_else(|| (uniforms::tile_cfa(RGGB), 2)),
+                white_balance::multipliers(
+                    raw,
+                    settings.white_balance,
+                    settings.temperature,
+                    settings.tint,
+                ),
             ),
         };
 
+        Uniforms {
+            mouse_pos: self.mouse_pos,
+            scroll_delta: self.scroll_delta,
+            window_size,
+            image_size,
+            cam_2_xyz,
+            xyz_2_output: color::to_columns(settings.output_color_space.xyz_to_rgb()),
+            output_2_display: color::to_columns(settings.output_color_space.rgb_to_srgb()),
+            whitelevels,
+            blacklevels,
+            crops,
+            exposure: self.settings.exposure,
+            contrast: self.settings.contrast,
+            cfa,
+            cfa_size,
+            orientation: self.orientation,
+            wb_multipliers,
+            transfer: settings.output_color_space.transfer(),
+            input_kind,
+            highlight_recovery: settings.highlight_recovery,
+            tone_mapping: settings.tone_mapping,
+            filmic_white: settings.filmic_white,
+            filmic_black: settings.filmic_black,
+            lut_size: self.lut().map_or(0, |lut| lut.size as u32),
+            lut_domain_min: self.lut().map_or([0.0; 3], |lut| lut.domain_min),
+            lut_domain_max: self.lut().map_or([1.0; 3], |lut| lut.domain_max),
+            lut_intensity: settings.lut_intensity,
+            lut_interpolation: settings.lut_interpolation,
+            hsl: settings.hsl,
+            saturation: settings.saturation,
+            vibrance: settings.vibrance,
+        }
+    }
+
+    /// Temperature and tint of the current white balance, for the sliders
+    pub fn temperature_tint(&self) -> (f32, f32) {
+        let custom = (self.settings.temperature, self.settings.tint);
+        match (self.settings.white_balance, &*self.image) {
+            (WhiteBalance::Custom, _) => custom,
+            (WhiteBalance::AsShot, Image::RawImage(raw)) => {
+                white_balance::as_shot(raw).unwrap_or(custom)
+            }
+            (balance, _) => balance.preset().unwrap_or(custom),
+        }
+    }
+
+    /// Sets a custom white balance that makes the area under the mouse
+    /// neutral, for the image displayed at `display_size`.
+    pub fn pick_white(&mut self, display_size: iced::Size<f32>) -> crate::Result<()> {
+        let Image::RawImage(raw) = &*self.image else {
+            return Err("White balance can only be picked on raw images".into());
+        };
+        let position = iced::Point::new(
+            self.mouse_pos.0 / display_size.width,
+            self.mouse_pos.1 / display_size.height,
+        );
+        if !(0.0..=1.0).contains(&position.x) || !(0.0..=1.0).contains(&position.y) {
+            return Err("Pick a point in the image".into());
+        }
+        let neutral = sample_neutral(raw, self.orientation.stored_position(position))
+            .ok_or("The picked area is clipped or too dark")?;
+        let (temperature, tint) = white_balance::neutral_temperature(raw, neutral);
+        self.settings.white_balance = WhiteBalance::Custom;
+        self.settings.temperature = temperature;
+        self.settings.tint = tint;
+        Ok(())
+    }
+}
+
+/// Averages the sensor values of each color around a position in the
+/// stored image, normalized to the area inside the crops. Clipped pixels
+/// don't show the color of the light, so they are left out.
+#[allow(clippy::cognitive_complexity)]
+fn sample_neutral(raw: &rawloader::RawImage, position: iced::Point) -> Option<[f32; 3]> {
+    // Raws are cached as decoded, so the samples may be either
+    let sample = |index| match &raw.data {
+        rawloader::RawImageData::Integer(items) => items.get(index).copied().map(f32::from),
+        rawloader::RawImageData::Float(items) => items.get(index).copied(),
+    };
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right) as f32;
+    let height = raw.height.saturating_sub(top + bottom) as f32;
+    let x = left + (position.x * width) as usize;
+    let y = top + (position.y * height) as usize;
+
+    let mut sums = [0.0; 3];
+    let mut counts = [0.0; 3];
+    for row in y.saturating_sub(PICK_RADIUS)..(y + PICK_RADIUS + 1).min(raw.height) {
+        for col in x.saturating_sub(PICK_RADIUS)..(x + PICK_RADIUS + 1).min(raw.width) {
+            let color = match raw.cfa.color_at(row, col) {
+                3 => 1,
+                c => c,
+            };
+            let value = sample(row * raw.width + col)?;
+            if value >= f32::from(raw.whitelevels[color]) {
+                continue;
+            }
+            sums[color] += value - f32::from(raw.blacklevels[color]);
+            counts[color] += 1.0;
+        }
+    }
+    let mean: [f32; 3] = std::array::from_fn(|c| sums[c] / counts[c]);
+    mean.iter().all(|v| *v > 0.0).then_some(mean)
+}
+
+impl iced::widget::shader::Program<Message> for Program {
+    type State = ();
+
+    type Primitive = Primitive;
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        _cursor: iced::mouse::Cursor,
+        bounds: iced::Rectangle,
+    ) -> Self::Primitive {
         Primitive {
-            uniforms: Uniforms {
-                mouse_pos: self.mouse_pos,
-                scroll_delta: self.scroll_delta,
-                window_size: bounds.size(),
-                image_size,
-                cam_2_xyz,
-                xyz_2_srgb,
-                whitelevels,
-                blacklevels,
-                crops,
-                exposure: self.exposure,
-                contrast: self.contrast,
-            },
+            uniforms: self.uniforms(bounds.size()),
             image_path: self.image_path.clone(),
             image: self.image.clone(),
+            demosaic_algorithm: self.settings.demosaic_algorithm,
+            curves: self.settings.curves.clone(),
+            lut: self.lut.as_ref().map(|(_, lut)| lut.clone()),
         }
     }
 }
 
+const RGGB: [u32; 4] = [0, 1, 1, 2];
+
+/// Color indices of the sensor's CFA tiled to 6x6, along with its repeat
+/// period. rawloader reports a second green (or emerald) as color 3, which
+/// the demosaic shaders treat as plain green. Patterns that don't tile into
+/// 6x6 have no layout.
+fn cfa_pattern(cfa: &rawloader::CFA) -> Option<([[u32; 6]; 6], u32)> {
+    if cfa.width == 0 || cfa.height == 0 || 6 % cfa.width != 0 || 6 % cfa.height != 0 {
+        return None;
+    }
+    let pattern = std::array::from_fn(|row| {
+        std::array::from_fn(|col| match cfa.color_at(row, col) {
+            3 => 1,
+            c => c as u32,
+        })
+    });
+    Some((pattern, cfa.width.max(cfa.height) as u32))
+}
+
+/// Widens a matrix to the four camera channels of `cam_2_xyz`
+fn pad_rows(matrix: color::Matrix) -> [[f32; 4]; 3] {
+    matrix.map(|row| [row[0] as f32, row[1] as f32, row[2] as f32, 0.0])
+}
+
 const fn to_float(arr: [u16; 4]) -> [f32; 4] {
     [arr[0] as f32, arr[1] as f32, arr[2] as f32, arr[3] as f32]
 }
@@ -161,6 +497,76 @@ mod tests {
 
     extern crate test;
 
+    #[test]
+    fn test_cfa_pattern() {
+        for (name, pattern) in [
+            ("RGGB", [0, 1, 1, 2]),
+            ("GRBG", [1, 0, 2, 1]),
+            ("GBRG", [1, 2, 0, 1]),
+            ("BGGR", [2, 1, 1, 0]),
+        ] {
+            let cfa = cfa_pattern(&rawloader::CFA::new(name));
+            assert_eq!(cfa, Some((uniforms::tile_cfa(pattern), 2)), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_xtrans_cfa_pattern() {
+        let (pattern, size) =
+            cfa_pattern(&rawloader::CFA::new("GGRGGBGGBGGRBRGRBGGGBGGRGGRGGBRBGBRG")).unwrap();
+        assert_eq!(size, 6);
+        assert_eq!(pattern[0], [1, 1, 0, 1, 1, 2]);
+        assert_eq!(pattern[2], [2, 0, 1, 0, 2, 1]);
+    }
+
+    #[test]
+    fn test_unsupported_cfa_pattern() {
+        // A 4x4 pattern doesn't tile into the 6x6 layout
+        assert_eq!(cfa_pattern(&rawloader::CFA::new("RGBGGRGBBGRGGBGR")), None);
+        assert_eq!(cfa_pattern(&rawloader::CFA::new("")), None);
+    }
+
+    #[test]
+    fn test_images_are_linearised_from_srgb() {
+        let uniforms = Program::default().uniforms(iced::Size::new(1.0, 1.0));
+        assert_eq!(uniforms.input_kind, InputKind::Srgb);
+        // sRGB white has a luminance of one
+        let luminance: f32 = uniforms.cam_2_xyz[1].iter().sum();
+        assert!((luminance - 1.0).abs() < 1e-4, "{luminance}");
+    }
+
+    #[test]
+    fn test_float_images_stay_linear() {
+        let image = image::DynamicImage::new_rgb32f(1, 1);
+        let program = Program {
+            image: Arc::new(Image::from(image).prepare().unwrap()),
+            ..Program::default()
+        };
+        let uniforms = program.uniforms(iced::Size::new(1.0, 1.0));
+        assert_eq!(uniforms.input_kind, InputKind::Linear);
+        // Highlights above 1 survive
+        assert!(uniforms.whitelevels.iter().all(|&white| white > 1.0));
+
+        let encoded = Image::from(image::DynamicImage::new_rgb16(1, 1));
+        assert!(matches!(encoded.prepare().unwrap(), Image::DynamicImage(_)));
+    }
+
+    #[test]
+    fn test_lut_path_is_relative_to_the_image() {
+        let program = Program {
+            image_path: PathBuf::from("shoot/IMG_0042.CR2"),
+            ..Program::default()
+        };
+        assert_eq!(
+            program.lut_path(Path::new("looks/film.cube")),
+            Path::new("shoot/looks/film.cube")
+        );
+        assert_eq!(
+            program.lut_path(Path::new("/looks/film.cube")),
+            Path::new("/looks/film.cube")
+        );
+    }
+
     #[bench]
     fn test_clone_image(b: &mut test::Bencher) {
         let img_path = PathBuf::from("assets/IMG_7679.jpg");
@@ -1,10 +1,17 @@
-use std::path::PathBuf;
+use std::sync::{Arc, Weak};
 
 use crate::{
     compute::{
-        demosaic::DemosaicShader, downsample::DownsampleShader, fragment::FragmentShader,
-        processing::ProcessingShader,
+        self,
+        demosaic::{DemosaicAlgorithm, DemosaicShader},
+        downsample::DownsampleShader,
+        fragment::FragmentShader,
+        hsl::HslShader,
+        processing::{self, ProcessingShader},
     },
+    cube,
+    curve::Curves,
+    program,
     uniforms::Uniforms,
     util::Tof32,
 };
@@ -13,10 +20,20 @@ pub struct ComputeRenderer {
     pub fragment_shader: RenderShaderData,
     pub uniforms: wgpu::Buffer,
     pub demosaic_shader: ComputeShaderData,
+    pub demosaic_algorithm: DemosaicAlgorithm,
+    pub xtrans_shader: ComputeShaderData,
     pub downsample_shader: ComputeShaderData,
     pub processing_shader: ComputeShaderData,
-    pub image_path: PathBuf,
+    pub hsl_shader: ComputeShaderData,
+    pub encode_shader: ComputeShaderData,
+    /// The image the textures were created from, compared by identity
+    pub image: Weak<program::Image>,
     pub textures: Textures,
+    /// The curves in the LUT texture, `None` until they are written to a
+    /// newly created one
+    pub curves: Option<Curves>,
+    /// The LUT in `textures.lut`, if one was uploaded to it
+    pub lut: Option<Arc<cube::Lut>>,
 }
 
 pub struct ComputeShaderData {
@@ -37,11 +54,52 @@ pub struct Textures {
     pub full_output_texture: wgpu::Texture,
     pub input_texture: wgpu::Texture,
     pub output_texture: wgpu::Texture,
+    pub curve_lut: wgpu::Texture,
+    /// A placeholder until a 3D LUT is uploaded
+    pub lut: wgpu::Texture,
     #[allow(dead_code)]
     pub image_size: iced::Size<u32>,
     pub output_size: iced::Size<u32>,
 }
 
+impl Textures {
+    /// Creates the textures for processing the image at `output_size`,
+    /// without uploading its pixels.
+    pub fn new(
+        device: &wgpu::Device,
+        image: &program::Image,
+        output_size: iced::Size<u32>,
+    ) -> Self {
+        let image_size = iced::Size::new(image.width(), image.height());
+        let full_texture = match image {
+            program::Image::DynamicImage(dynamic_image)
+            | program::Image::LinearImage(dynamic_image)
+            | program::Image::Preview(dynamic_image) => {
+                compute::create_texture(device, dynamic_image)
+            }
+            program::Image::RawImage(_) => {
+                compute::create_float_texture(device, image_size, wgpu::TextureFormat::R32Float)
+            }
+        };
+        let full_output_texture =
+            compute::create_float_texture(device, image_size, wgpu::TextureFormat::Rgba32Float);
+        let input_texture =
+            compute::create_float_texture(device, output_size, wgpu::TextureFormat::Rgba32Float);
+        let output_texture =
+            compute::create_float_texture(device, output_size, wgpu::TextureFormat::Rgba32Float);
+        Self {
+            full_texture,
+            full_output_texture,
+            input_texture,
+            output_texture,
+            curve_lut: processing::create_curve_texture(device),
+            lut: processing::create_lut_texture(device, 1),
+            image_size,
+            output_size,
+        }
+    }
+}
+
 impl ComputeRenderer {
     pub fn replace_bind_groups(&mut self, device: &wgpu::Device) {
         let (fragment_bind_group, fragment_uniform_bind_group) = FragmentShader::create_bind_group(
@@ -57,6 +115,18 @@ impl ComputeRenderer {
                 &self.uniforms,
                 &self.textures,
             );
+        let (hsl_bind_group, hsl_uniform_bind_group) = HslShader::create_bind_group(
+            device,
+            &self.hsl_shader.pipeline,
+            &self.uniforms,
+            &self.textures,
+        );
+        let (encode_bind_group, encode_uniform_bind_group) = ProcessingShader::create_bind_group(
+            device,
+            &self.encode_shader.pipeline,
+            &self.uniforms,
+            &self.textures,
+        );
         let (downsample_bind_group, downsample_uniform_bind_group) =
             DownsampleShader::create_bind_group(
                 device,
@@ -70,17 +140,38 @@ impl ComputeRenderer {
             &self.uniforms,
             &self.textures,
         );
+        let (xtrans_bind_group, xtrans_uniform_bind_group) = DemosaicShader::create_bind_group(
+            device,
+            &self.xtrans_shader.pipeline,
+            &self.uniforms,
+            &self.textures,
+        );
         self.fragment_shader.bind_group = fragment_bind_group;
         self.fragment_shader.uniform_bind_group = fragment_uniform_bind_group;
         self.processing_shader.bind_group = processing_bind_group;
+        self.hsl_shader.bind_group = hsl_bind_group;
+        self.encode_shader.bind_group = encode_bind_group;
         self.downsample_shader.bind_group = downsample_bind_group;
         self.demosaic_shader.bind_group = demosaic_bind_group;
+        self.xtrans_shader.bind_group = xtrans_bind_group;
         self.processing_shader.uniform_bind_group = processing_uniform_bind_group;
+        self.hsl_shader.uniform_bind_group = hsl_uniform_bind_group;
+        self.encode_shader.uniform_bind_group = encode_uniform_bind_group;
         self.downsample_shader.uniform_bind_group = downsample_uniform_bind_group;
         self.demosaic_shader.uniform_bind_group = demosaic_uniform_bind_group;
+        self.xtrans_shader.uniform_bind_group = xtrans_uniform_bind_group;
         self.processing_shader.size = self.textures.output_size;
+        self.hsl_shader.size = self.textures.output_size;
+        self.encode_shader.size = self.textures.output_size;
         self.downsample_shader.size = self.textures.output_size;
         self.demosaic_shader.size = self.textures.image_size;
+        self.xtrans_shader.size = self.textures.image_size;
+    }
+
+    pub fn set_demosaic_algorithm(&mut self, device: &wgpu::Device, algorithm: DemosaicAlgorithm) {
+        self.demosaic_shader =
+            DemosaicShader::compile(device, &self.uniforms, &self.textures, algorithm);
+        self.demosaic_algorithm = algorithm;
     }
 
     pub fn copy_uniforms_to_device(&self, queue: &wgpu::Queue, uniforms: &Uniforms) {
@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::OutputColorSpace,
+    compute::{
+        demosaic::DemosaicAlgorithm,
+        processing::{HighlightRecovery, LutInterpolation, ToneMapping},
+    },
+    curve::Curves,
+    hsl::Hsl,
+    white_balance::WhiteBalance,
+};
+
+/// Every adjustment that can be made to an image. Missing fields fall back
+/// to their defaults when deserialising, so presets only need to name what
+/// they change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// In stops
+    pub exposure: f32,
+    pub contrast: f32,
+    pub demosaic_algorithm: DemosaicAlgorithm,
+    pub highlight_recovery: HighlightRecovery,
+    pub white_balance: WhiteBalance,
+    /// In kelvin, only used for the custom white balance
+    pub temperature: f32,
+    pub tint: f32,
+    /// Scales the chroma of every color, -1 removes it
+    pub saturation: f32,
+    /// Like saturation, but weighted towards muted colors
+    pub vibrance: f32,
+    pub hsl: Hsl,
+    pub tone_mapping: ToneMapping,
+    /// Scene-linear value that the filmic curve maps to white
+    pub filmic_white: f32,
+    /// Scene-linear value that the filmic curve maps to black
+    pub filmic_black: f32,
+    pub curves: Curves,
+    /// A `.cube` file applied after tone mapping
+    #[serde(with = "optional_path")]
+    pub lut: Option<PathBuf>,
+    /// How much of the LUT is mixed in, from 0 to 1
+    pub lut_intensity: f32,
+    pub lut_interpolation: LutInterpolation,
+    pub output_color_space: OutputColorSpace,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            contrast: 1.0,
+            demosaic_algorithm: DemosaicAlgorithm::default(),
+            highlight_recovery: HighlightRecovery::default(),
+            white_balance: WhiteBalance::default(),
+            temperature: 5500.0,
+            tint: 0.0,
+            saturation: 0.0,
+            vibrance: 0.0,
+            hsl: Hsl::default(),
+            tone_mapping: ToneMapping::default(),
+            filmic_white: 11.2,
+            filmic_black: 0.0,
+            curves: Curves::default(),
+            lut: None,
+            lut_intensity: 1.0,
+            lut_interpolation: LutInterpolation::default(),
+            output_color_space: OutputColorSpace::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads a preset from a TOML file. A relative LUT path is relative to
+    /// the folder of the preset.
+    pub fn load(path: &Path) -> crate::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut settings: Self = toml::from_str(&text)?;
+        settings.lut = settings
+            .lut
+            .map(|lut| crate::loader::parent_folder(path).join(lut));
+        Ok(settings)
+    }
+}
+
+/// TOML has no null, so a missing path is written as an empty string.
+/// Leaving the key out instead would keep the path a sidecar had before.
+mod optional_path {
+    use std::path::PathBuf;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // serde hands over a reference to the field
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(path) => path.serialize(serializer),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        let path = PathBuf::deserialize(deserializer)?;
+        Ok((!path.as_os_str().is_empty()).then_some(path))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_preset() {
+        let settings: Settings =
+            toml::from_str("exposure = 1.5\ndemosaic_algorithm = \"vng\"").unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                exposure: 1.5,
+                demosaic_algorithm: DemosaicAlgorithm::Vng,
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_partial_hsl() {
+        let settings: Settings =
+            toml::from_str("vibrance = 0.25\n\n[hsl.blue]\nsaturation = -0.5").unwrap();
+        let mut expected = Settings {
+            vibrance: 0.25,
+            ..Settings::default()
+        };
+        expected.hsl.blue.saturation = -0.5;
+        assert_eq!(settings, expected);
+    }
+
+    #[test]
+    fn test_preset_lut_is_relative_to_the_preset() {
+        let dir = std::env::temp_dir().join("wgpu_compute_test_preset");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("preset.toml");
+        std::fs::write(&path, "lut = \"looks/film.cube\"").unwrap();
+        let settings = Settings::load(&path).unwrap();
+        assert_eq!(settings.lut, Some(dir.join("looks/film.cube")));
+    }
+}
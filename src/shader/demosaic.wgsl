@@ -6,26 +6,6 @@ var image: texture_2d<f32>;
 @binding(1)
 var output: texture_storage_2d<rgba32float, write>;
 
-struct Uniforms {
-    cam_2_xyz: mat3x4<f32>,
-    xyz_2_srgb: mat3x3<f32>,
-    whitelevels: vec4<f32>,
-    blacklevels: vec4<f32>,
-    crops: vec4<u32>,
-    mouse_pos: vec2<f32>,
-    window_size: vec2<f32>,
-    image_size: vec2<f32>,
-    output_size: vec2<f32>,
-    scroll_delta: f32,
-    exposure: f32,
-    contrast: f32,
-    // _padding: vec3<f32>,
-};
-
-@group(1)
-@binding(0)
-var<uniform> uniforms: Uniforms;
-
 @compute
 @workgroup_size(16, 16)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
@@ -35,7 +15,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    if uniforms.xyz_2_srgb[0].x == 1.0 {
+    if uniforms.input_kind != INPUT_RAW {
         let color = textureLoad(image, coords, 0);
         textureStore(output, coords, color);
         return;
@@ -43,9 +23,29 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
 
 
     var color = demosaic(coords);
+    // Processing runs on the downsampled image, which can't tell which of
+    // the interpolated channels came from clipped sensor values
+    color.a = clip_mask(coords);
     textureStore(output, coords, color);
 }
 
+// Bit c is set when a sensor value of color c in the 3x3 neighborhood of p
+// is at its white level
+fn clip_mask(p: vec2<i32>) -> f32 {
+    var mask = 0u;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let q = p + vec2<i32>(x, y);
+            let c = cfa_color(q);
+            let black = uniforms.blacklevels[c];
+            if load1(mirror(q)) >= black + 0.99 * (uniforms.whitelevels[c] - black) {
+                mask |= 1u << c;
+            }
+        }
+    }
+    return f32(mask);
+}
+
 
 fn in_bounds(p: vec2<i32>, size: vec2<i32>) -> bool {
     return p.x >= 0 && p.y >= 0 && p.x < size.x && p.y < size.y;
@@ -55,97 +55,48 @@ fn load1(p: vec2<i32>) -> f32 {
     return textureLoad(image, p, 0).r;
 }
 
-fn avg_cross(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
-    var sum: f32 = 0.0;
-    var n: i32 = 0;
-    let L = p + vec2<i32>(-1, 0);
-    let R = p + vec2<i32>(1, 0);
-    let U = p + vec2<i32>(0, -1);
-    let D = p + vec2<i32>(0, 1);
-    if in_bounds(L, size) { sum += load1(L); n += 1; }
-    if in_bounds(R, size) { sum += load1(R); n += 1; }
-    if in_bounds(U, size) { sum += load1(U); n += 1; }
-    if in_bounds(D, size) { sum += load1(D); n += 1; }
-    if n == 0 { return center; }
-    return sum / f32(n);
+fn image_size() -> vec2<i32> {
+    return vec2<i32>(i32(uniforms.image_size.x), i32(uniforms.image_size.y));
 }
 
-fn avg_diag(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
-    var sum: f32 = 0.0;
-    var n: i32 = 0;
-    let UL = p + vec2<i32>(-1, -1);
-    let UR = p + vec2<i32>(1, -1);
-    let DL = p + vec2<i32>(-1, 1);
-    let DR = p + vec2<i32>(1, 1);
-    if in_bounds(UL, size) { sum += load1(UL); n += 1; }
-    if in_bounds(UR, size) { sum += load1(UR); n += 1; }
-    if in_bounds(DL, size) { sum += load1(DL); n += 1; }
-    if in_bounds(DR, size) { sum += load1(DR); n += 1; }
-    if n == 0 { return center; }
-    return sum / f32(n);
+// Mirrors out of bounds coordinates back into the image. Reflecting around
+// the edge pixel keeps the parity, so the CFA color is preserved.
+fn mirror(p: vec2<i32>) -> vec2<i32> {
+    let size = image_size();
+    let q = abs(p);
+    return clamp(select(q, 2 * (size - 1) - q, q >= size), vec2<i32>(0), size - 1);
 }
 
-fn avg_lr(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
-    var sum: f32 = 0.0;
-    var n: i32 = 0;
-    let L = p + vec2<i32>(-1, 0);
-    let R = p + vec2<i32>(1, 0);
-    if in_bounds(L, size) { sum += load1(L); n += 1; }
-    if in_bounds(R, size) { sum += load1(R); n += 1; }
-    if n == 0 { return center; }
-    return sum / f32(n);
+// Loads the sensor value at p + offset, mirrored at the image edges
+fn px(p: vec2<i32>, offset: vec2<i32>) -> f32 {
+    return load1(mirror(p + offset));
 }
 
-fn avg_ud(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
-    var sum: f32 = 0.0;
-    var n: i32 = 0;
-    let U = p + vec2<i32>(0, -1);
-    let D = p + vec2<i32>(0, 1);
-    if in_bounds(U, size) { sum += load1(U); n += 1; }
-    if in_bounds(D, size) { sum += load1(D); n += 1; }
-    if n == 0 { return center; }
-    return sum / f32(n);
+// color index (0 = R, 1 = G, 2 = B) of the sensor pixel loaded for p
+fn cfa_color(p: vec2<i32>) -> u32 {
+    let q = mirror(p) % 6;
+    let index = q.y * 6 + q.x;
+    return uniforms.cfa[index / 4][index % 4];
 }
 
-fn demosaic(coords: vec2<i32>) -> vec4<f32> {
-    let size = vec2<i32>(i32(uniforms.image_size.x), i32(uniforms.image_size.y));
-    let x = coords.x;
-    let y = coords.y;
-    let p = vec2<i32>(x, y);
-
-    let c = load1(p);
-
-    var r: f32 = 0.0;
-    var g: f32 = 0.0;
-    var b: f32 = 0.0;
-
-    if y % 2 == 0 {
-        if x % 2 == 0 {
-            // R location
-            r = c;
-            g = avg_cross(p, size, c);
-            b = avg_diag(p, size, c);
-        } else {
-            // G on R row
-            r = avg_lr(p, size, c);
-            g = c;
-            b = avg_ud(p, size, c);
-        }
-    } else {
-        if x % 2 == 0 {
-            // G on B row
-            r = avg_ud(p, size, c);
-            g = c;
-            b = avg_lr(p, size, c);
-        } else {
-            // B location
-            r = avg_diag(p, size, c);
-            g = avg_cross(p, size, c);
-            b = c;
-        }
+fn lab_f(t: f32) -> f32 {
+    if t > 0.008856 {
+        return pow(t, 1.0 / 3.0);
     }
+    return 7.787 * t + 16.0 / 116.0;
+}
 
-    return vec4<f32>(r, g, b, 1.0);
+// CIELab, treating the camera values as roughly sRGB primaries
+fn to_lab(rgb: vec3<f32>) -> vec3<f32> {
+    let white = max(max(uniforms.whitelevels.x, uniforms.whitelevels.y), 1.0);
+    let c = rgb / white;
+    let xyz = vec3<f32>(
+        dot(c, vec3<f32>(0.412453, 0.357580, 0.180423)) / 0.950456,
+        dot(c, vec3<f32>(0.212671, 0.715160, 0.072169)),
+        dot(c, vec3<f32>(0.019334, 0.119193, 0.950227)) / 1.088754,
+    );
+    let f = vec3<f32>(lab_f(xyz.x), lab_f(xyz.y), lab_f(xyz.z));
+    return vec3<f32>(116.0 * f.y - 16.0, 500.0 * (f.x - f.y), 200.0 * (f.y - f.z));
 }
 
 /*
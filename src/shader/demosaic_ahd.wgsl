@@ -0,0 +1,106 @@
+// Adaptive Homogeneity-Directed demosaicing (Hirakawa and Parks), in a
+// single pass. Every pixel is interpolated both horizontally and vertically,
+// and the candidate whose CIELab neighborhood is most homogeneous wins.
+
+const AHD_HORIZONTAL = vec2<i32>(1, 0);
+const AHD_VERTICAL = vec2<i32>(0, 1);
+
+// Green at p, interpolated along d with a Laplacian correction term
+fn ahd_green(p: vec2<i32>, d: vec2<i32>) -> f32 {
+    let c = px(p, vec2<i32>(0, 0));
+    if cfa_color(p) == 1u {
+        return c;
+    }
+    let a = px(p, -d);
+    let b = px(p, d);
+    let g = (a + b) / 2.0 + (2.0 * c - px(p, -2 * d) - px(p, 2 * d)) / 4.0;
+    return clamp(g, min(a, b), max(a, b));
+}
+
+// Full color at p, with all green estimates taken along d
+fn ahd_rgb(p: vec2<i32>, d: vec2<i32>) -> vec3<f32> {
+    let c = px(p, vec2<i32>(0, 0));
+    var rgb = vec3<f32>(0.0);
+    let color = cfa_color(p);
+    if color == 1u {
+        let l = vec2<i32>(-1, 0);
+        let r = vec2<i32>(1, 0);
+        let u = vec2<i32>(0, -1);
+        let dn = vec2<i32>(0, 1);
+        let lr = cfa_color(p + r);
+        rgb[1] = c;
+        rgb[lr] = c + (px(p, l) - ahd_green(p + l, d) + px(p, r) - ahd_green(p + r, d)) / 2.0;
+        rgb[2u - lr] = c + (px(p, u) - ahd_green(p + u, d) + px(p, dn) - ahd_green(p + dn, d)) / 2.0;
+    } else {
+        let g = ahd_green(p, d);
+        var diff = 0.0;
+        for (var y = -1; y <= 1; y += 2) {
+            for (var x = -1; x <= 1; x += 2) {
+                let q = vec2<i32>(x, y);
+                diff += px(p, q) - ahd_green(p + q, d);
+            }
+        }
+        rgb[1] = g;
+        rgb[color] = c;
+        rgb[2u - color] = g + diff / 4.0;
+    }
+    return max(rgb, vec3<f32>(0.0));
+}
+
+fn chroma_distance(a: vec3<f32>, b: vec3<f32>) -> f32 {
+    return dot(a.yz - b.yz, a.yz - b.yz);
+}
+
+fn demosaic(coords: vec2<i32>) -> vec4<f32> {
+    let p = coords;
+    var neighbors = array<vec2<i32>, 4>(
+        vec2<i32>(-1, 0),
+        vec2<i32>(1, 0),
+        vec2<i32>(0, -1),
+        vec2<i32>(0, 1),
+    );
+
+    let rgb_h = ahd_rgb(p, AHD_HORIZONTAL);
+    let rgb_v = ahd_rgb(p, AHD_VERTICAL);
+    let lab_h = to_lab(rgb_h);
+    let lab_v = to_lab(rgb_v);
+
+    var neighbors_h: array<vec3<f32>, 4>;
+    var neighbors_v: array<vec3<f32>, 4>;
+    for (var i = 0; i < 4; i++) {
+        neighbors_h[i] = to_lab(ahd_rgb(mirror(p + neighbors[i]), AHD_HORIZONTAL));
+        neighbors_v[i] = to_lab(ahd_rgb(mirror(p + neighbors[i]), AHD_VERTICAL));
+    }
+
+    // Adaptive thresholds: the luminance and chroma variation along each
+    // candidate's own interpolation direction
+    let eps_l = min(
+        max(abs(lab_h.x - neighbors_h[0].x), abs(lab_h.x - neighbors_h[1].x)),
+        max(abs(lab_v.x - neighbors_v[2].x), abs(lab_v.x - neighbors_v[3].x)),
+    );
+    let eps_c = min(
+        max(chroma_distance(lab_h, neighbors_h[0]), chroma_distance(lab_h, neighbors_h[1])),
+        max(chroma_distance(lab_v, neighbors_v[2]), chroma_distance(lab_v, neighbors_v[3])),
+    );
+
+    var homogeneity_h = 0;
+    var homogeneity_v = 0;
+    for (var i = 0; i < 4; i++) {
+        if abs(lab_h.x - neighbors_h[i].x) <= eps_l
+            && chroma_distance(lab_h, neighbors_h[i]) <= eps_c {
+            homogeneity_h += 1;
+        }
+        if abs(lab_v.x - neighbors_v[i].x) <= eps_l
+            && chroma_distance(lab_v, neighbors_v[i]) <= eps_c {
+            homogeneity_v += 1;
+        }
+    }
+
+    var rgb = (rgb_h + rgb_v) / 2.0;
+    if homogeneity_h > homogeneity_v {
+        rgb = rgb_h;
+    } else if homogeneity_v > homogeneity_h {
+        rgb = rgb_v;
+    }
+    return vec4<f32>(rgb, 1.0);
+}
@@ -0,0 +1,81 @@
+fn avg_cross(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
+    var sum: f32 = 0.0;
+    var n: i32 = 0;
+    let L = p + vec2<i32>(-1, 0);
+    let R = p + vec2<i32>(1, 0);
+    let U = p + vec2<i32>(0, -1);
+    let D = p + vec2<i32>(0, 1);
+    if in_bounds(L, size) { sum += load1(L); n += 1; }
+    if in_bounds(R, size) { sum += load1(R); n += 1; }
+    if in_bounds(U, size) { sum += load1(U); n += 1; }
+    if in_bounds(D, size) { sum += load1(D); n += 1; }
+    if n == 0 { return center; }
+    return sum / f32(n);
+}
+
+fn avg_diag(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
+    var sum: f32 = 0.0;
+    var n: i32 = 0;
+    let UL = p + vec2<i32>(-1, -1);
+    let UR = p + vec2<i32>(1, -1);
+    let DL = p + vec2<i32>(-1, 1);
+    let DR = p + vec2<i32>(1, 1);
+    if in_bounds(UL, size) { sum += load1(UL); n += 1; }
+    if in_bounds(UR, size) { sum += load1(UR); n += 1; }
+    if in_bounds(DL, size) { sum += load1(DL); n += 1; }
+    if in_bounds(DR, size) { sum += load1(DR); n += 1; }
+    if n == 0 { return center; }
+    return sum / f32(n);
+}
+
+fn avg_lr(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
+    var sum: f32 = 0.0;
+    var n: i32 = 0;
+    let L = p + vec2<i32>(-1, 0);
+    let R = p + vec2<i32>(1, 0);
+    if in_bounds(L, size) { sum += load1(L); n += 1; }
+    if in_bounds(R, size) { sum += load1(R); n += 1; }
+    if n == 0 { return center; }
+    return sum / f32(n);
+}
+
+fn avg_ud(p: vec2<i32>, size: vec2<i32>, center: f32) -> f32 {
+    var sum: f32 = 0.0;
+    var n: i32 = 0;
+    let U = p + vec2<i32>(0, -1);
+    let D = p + vec2<i32>(0, 1);
+    if in_bounds(U, size) { sum += load1(U); n += 1; }
+    if in_bounds(D, size) { sum += load1(D); n += 1; }
+    if n == 0 { return center; }
+    return sum / f32(n);
+}
+
+fn demosaic(coords: vec2<i32>) -> vec4<f32> {
+    let size = vec2<i32>(i32(uniforms.image_size.x), i32(uniforms.image_size.y));
+    let x = coords.x;
+    let y = coords.y;
+    let p = vec2<i32>(x, y);
+
+    let c = load1(p);
+
+    var rgb = vec3<f32>(0.0);
+
+    switch cfa_color(p) {
+        case 0u, 2u: {
+            // R or B location, the opposite color sits on the diagonals
+            let color = cfa_color(p);
+            rgb[color] = c;
+            rgb[1] = avg_cross(p, size, c);
+            rgb[2u - color] = avg_diag(p, size, c);
+        }
+        default: {
+            // G location, the neighbor to the right tells which row we're on
+            let lr = cfa_color(p + vec2<i32>(1, 0));
+            rgb[lr] = avg_lr(p, size, c);
+            rgb[1] = c;
+            rgb[2u - lr] = avg_ud(p, size, c);
+        }
+    }
+
+    return vec4<f32>(rgb, 1.0);
+}
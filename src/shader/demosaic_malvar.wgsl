@@ -0,0 +1,31 @@
+// Malvar-He-Cutler gradient-corrected linear interpolation, using the 5x5
+// kernels from "High-quality linear interpolation for demosaicing of
+// Bayer-patterned color images" (2004).
+fn demosaic(coords: vec2<i32>) -> vec4<f32> {
+    let p = coords;
+    let c = px(p, vec2<i32>(0, 0));
+
+    let h1 = px(p, vec2<i32>(-1, 0)) + px(p, vec2<i32>(1, 0));
+    let v1 = px(p, vec2<i32>(0, -1)) + px(p, vec2<i32>(0, 1));
+    let h2 = px(p, vec2<i32>(-2, 0)) + px(p, vec2<i32>(2, 0));
+    let v2 = px(p, vec2<i32>(0, -2)) + px(p, vec2<i32>(0, 2));
+    let diag = px(p, vec2<i32>(-1, -1)) + px(p, vec2<i32>(1, -1))
+        + px(p, vec2<i32>(-1, 1)) + px(p, vec2<i32>(1, 1));
+
+    var rgb = vec3<f32>(0.0);
+    let color = cfa_color(p);
+    if color == 1u {
+        // G location, the neighbor to the right tells which row we're on
+        let lr = cfa_color(p + vec2<i32>(1, 0));
+        rgb[lr] = (5.0 * c + 4.0 * h1 - diag - h2 + 0.5 * v2) / 8.0;
+        rgb[1] = c;
+        rgb[2u - lr] = (5.0 * c + 4.0 * v1 - diag - v2 + 0.5 * h2) / 8.0;
+    } else {
+        // R or B location, the opposite color sits on the diagonals
+        rgb[color] = c;
+        rgb[1] = (4.0 * c + 2.0 * (h1 + v1) - (h2 + v2)) / 8.0;
+        rgb[2u - color] = (6.0 * c + 2.0 * diag - 1.5 * (h2 + v2)) / 8.0;
+    }
+
+    return vec4<f32>(max(rgb, vec3<f32>(0.0)), 1.0);
+}
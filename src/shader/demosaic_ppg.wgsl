@@ -0,0 +1,96 @@
+// Patterned Pixel Grouping (Chuan-kai Lin). The green plane is interpolated
+// along the smoothest of the four axis directions, then red and blue are
+// filled in with hue transitions along the green gradient. Neighboring green
+// estimates are recomputed instead of read back from an intermediate texture.
+
+// Green at p, interpolated along the direction with the smallest gradient
+fn ppg_green(p: vec2<i32>) -> f32 {
+    let c = px(p, vec2<i32>(0, 0));
+    if cfa_color(p) == 1u {
+        return c;
+    }
+    let n = px(p, vec2<i32>(0, -1));
+    let s = px(p, vec2<i32>(0, 1));
+    let w = px(p, vec2<i32>(-1, 0));
+    let e = px(p, vec2<i32>(1, 0));
+    let n2 = px(p, vec2<i32>(0, -2));
+    let s2 = px(p, vec2<i32>(0, 2));
+    let w2 = px(p, vec2<i32>(-2, 0));
+    let e2 = px(p, vec2<i32>(2, 0));
+
+    let d_n = abs(c - n2) * 2.0 + abs(n - s);
+    let d_s = abs(c - s2) * 2.0 + abs(n - s);
+    let d_w = abs(c - w2) * 2.0 + abs(w - e);
+    let d_e = abs(c - e2) * 2.0 + abs(w - e);
+
+    var best = d_n;
+    var g = (n * 3.0 + s + c - n2) / 4.0;
+    var lo = min(n, s);
+    var hi = max(n, s);
+    if d_e < best {
+        best = d_e;
+        g = (e * 3.0 + w + c - e2) / 4.0;
+        lo = min(w, e);
+        hi = max(w, e);
+    }
+    if d_w < best {
+        best = d_w;
+        g = (w * 3.0 + e + c - w2) / 4.0;
+        lo = min(w, e);
+        hi = max(w, e);
+    }
+    if d_s < best {
+        g = (s * 3.0 + n + c - s2) / 4.0;
+        lo = min(n, s);
+        hi = max(n, s);
+    }
+    return clamp(g, lo, hi);
+}
+
+fn hue_transit(l1: f32, l2: f32, l3: f32, v1: f32, v3: f32) -> f32 {
+    if (l1 < l2 && l2 < l3) || (l1 > l2 && l2 > l3) {
+        return v1 + (v3 - v1) * (l2 - l1) / (l3 - l1);
+    }
+    return (v1 + v3) / 2.0 + (l2 * 2.0 - l1 - l3) / 4.0;
+}
+
+fn demosaic(coords: vec2<i32>) -> vec4<f32> {
+    let p = coords;
+    let c = px(p, vec2<i32>(0, 0));
+    let g = ppg_green(p);
+
+    var rgb = vec3<f32>(0.0);
+    rgb[1] = g;
+    let color = cfa_color(p);
+    if color == 1u {
+        let l = vec2<i32>(-1, 0);
+        let r = vec2<i32>(1, 0);
+        let u = vec2<i32>(0, -1);
+        let d = vec2<i32>(0, 1);
+        let lr = cfa_color(p + r);
+        rgb[lr] = hue_transit(ppg_green(p + l), c, ppg_green(p + r), px(p, l), px(p, r));
+        rgb[2u - lr] = hue_transit(ppg_green(p + u), c, ppg_green(p + d), px(p, u), px(p, d));
+    } else {
+        let ne = vec2<i32>(1, -1);
+        let sw = vec2<i32>(-1, 1);
+        let nw = vec2<i32>(-1, -1);
+        let se = vec2<i32>(1, 1);
+        let g_ne = ppg_green(p + ne);
+        let g_sw = ppg_green(p + sw);
+        let g_nw = ppg_green(p + nw);
+        let g_se = ppg_green(p + se);
+        let d_ne = abs(px(p, ne) - px(p, sw)) + abs(px(p, 2 * ne) - c) + abs(c - px(p, 2 * sw))
+            + abs(g_ne - g) + abs(g - g_sw);
+        let d_nw = abs(px(p, nw) - px(p, se)) + abs(px(p, 2 * nw) - c) + abs(c - px(p, 2 * se))
+            + abs(g_nw - g) + abs(g - g_se);
+
+        rgb[color] = c;
+        if d_ne < d_nw {
+            rgb[2u - color] = hue_transit(g_ne, g, g_sw, px(p, ne), px(p, sw));
+        } else {
+            rgb[2u - color] = hue_transit(g_nw, g, g_se, px(p, nw), px(p, se));
+        }
+    }
+
+    return vec4<f32>(max(rgb, vec3<f32>(0.0)), 1.0);
+}
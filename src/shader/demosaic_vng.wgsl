@@ -0,0 +1,98 @@
+// Threshold-based Variable Number of Gradients (Chang, Cheung and Pang).
+// Eight directional gradients are measured over the 5x5 neighborhood, and
+// only the directions below the threshold contribute color differences.
+
+fn vng_directions() -> array<vec2<i32>, 8> {
+    return array<vec2<i32>, 8>(
+        vec2<i32>(0, -1),
+        vec2<i32>(1, -1),
+        vec2<i32>(1, 0),
+        vec2<i32>(1, 1),
+        vec2<i32>(0, 1),
+        vec2<i32>(-1, 1),
+        vec2<i32>(-1, 0),
+        vec2<i32>(-1, -1),
+    );
+}
+
+fn is_axis(d: vec2<i32>) -> bool {
+    return d.x == 0 || d.y == 0;
+}
+
+// Sum of absolute differences between same-colored pixels along d
+fn vng_gradient(p: vec2<i32>, d: vec2<i32>) -> f32 {
+    let zero = vec2<i32>(0, 0);
+    var gradient = abs(px(p, d) - px(p, -d)) + abs(px(p, 2 * d) - px(p, zero));
+    if is_axis(d) {
+        let perp = vec2<i32>(d.y, d.x);
+        gradient += 0.5 * (abs(px(p, d + perp) - px(p, perp - d))
+            + abs(px(p, d - perp) - px(p, -d - perp)));
+        gradient += 0.5 * (abs(px(p, 2 * d + perp) - px(p, perp))
+            + abs(px(p, 2 * d - perp) - px(p, -perp)));
+    } else if cfa_color(p) == 1u {
+        gradient += abs(px(p, d) - px(p, zero));
+    } else {
+        gradient += 0.5 * (abs(px(p, vec2<i32>(0, -d.y)) - px(p, vec2<i32>(d.x, 0)))
+            + abs(px(p, vec2<i32>(-d.x, 0)) - px(p, vec2<i32>(0, d.y))));
+    }
+    return gradient;
+}
+
+// Average of each color over the pixels lying in direction d
+fn vng_estimate(p: vec2<i32>, d: vec2<i32>) -> vec3<f32> {
+    let perp = vec2<i32>(d.y, d.x);
+    var offsets = array<vec2<i32>, 7>(
+        vec2<i32>(0, 0),
+        d,
+        2 * d,
+        d + perp,
+        d - perp,
+        perp,
+        -perp,
+    );
+    var count = 7;
+    if !is_axis(d) {
+        offsets[3] = vec2<i32>(d.x, 0);
+        offsets[4] = vec2<i32>(0, d.y);
+        count = 5;
+    }
+
+    var sum = vec3<f32>(0.0);
+    var n = vec3<f32>(0.0);
+    for (var i = 0; i < count; i++) {
+        let color = cfa_color(p + offsets[i]);
+        sum[color] += px(p, offsets[i]);
+        n[color] += 1.0;
+    }
+    return sum / max(n, vec3<f32>(1.0));
+}
+
+fn demosaic(coords: vec2<i32>) -> vec4<f32> {
+    let p = coords;
+    let c = px(p, vec2<i32>(0, 0));
+    var directions = vng_directions();
+
+    var gradients: array<f32, 8>;
+    var lo = 3.4e38;
+    var hi = 0.0;
+    for (var i = 0; i < 8; i++) {
+        gradients[i] = vng_gradient(p, directions[i]);
+        lo = min(lo, gradients[i]);
+        hi = max(hi, gradients[i]);
+    }
+    let threshold = 1.5 * lo + 0.5 * (hi - lo);
+
+    var sum = vec3<f32>(0.0);
+    var n = 0.0;
+    for (var i = 0; i < 8; i++) {
+        if gradients[i] <= threshold {
+            sum += vng_estimate(p, directions[i]);
+            n += 1.0;
+        }
+    }
+
+    let color = cfa_color(p);
+    var rgb = c + (sum - sum[color]) / n;
+    rgb[color] = c;
+    return vec4<f32>(max(rgb, vec3<f32>(0.0)), 1.0);
+}
@@ -0,0 +1,189 @@
+// Markesteijn's single pass demosaic for 6x6 X-Trans sensors. Every pixel
+// gets a full color candidate for each of four interpolation directions.
+// A homogeneity map counts, per direction, the neighbors whose candidates
+// change smoothly in CIELab, and the candidates of the most homogeneous
+// directions are averaged.
+
+fn xtrans_directions() -> array<vec2<i32>, 4> {
+    return array<vec2<i32>, 4>(
+        vec2<i32>(1, 0),
+        vec2<i32>(0, 1),
+        vec2<i32>(1, 1),
+        vec2<i32>(1, -1),
+    );
+}
+
+// Greens along one direction are cached for the 7x7 neighborhood, which
+// covers the 3x3 derivatives, their neighbors along the direction and the
+// color differences around those
+const XTRANS_REACH = 3;
+const XTRANS_WIDTH = 7;
+
+fn xtrans_index(offset: vec2<i32>) -> i32 {
+    return (offset.y + XTRANS_REACH) * XTRANS_WIDTH + offset.x + XTRANS_REACH;
+}
+
+// Green at p, between the nearest green pixels on either side along d. Away
+// from the mirrored edges, an X-Trans sensor has one within two pixels.
+fn xtrans_green(p: vec2<i32>, d: vec2<i32>) -> f32 {
+    if cfa_color(p) == 1u {
+        return px(p, vec2<i32>(0, 0));
+    }
+    var before = 0.0;
+    var after = 0.0;
+    var k_before = 0;
+    var k_after = 0;
+    for (var k = 1; k <= 3; k++) {
+        if k_before == 0 && cfa_color(p - k * d) == 1u {
+            before = px(p, -k * d);
+            k_before = k;
+        }
+        if k_after == 0 && cfa_color(p + k * d) == 1u {
+            after = px(p, k * d);
+            k_after = k;
+        }
+    }
+    if k_before == 0 || k_after == 0 {
+        return xtrans_green_mean(p);
+    }
+    return (before * f32(k_after) + after * f32(k_before)) / f32(k_before + k_after);
+}
+
+// Fallback for CFA patterns that lack a green along some direction
+fn xtrans_green_mean(p: vec2<i32>) -> f32 {
+    var sum = 0.0;
+    var count = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            if cfa_color(p + vec2<i32>(x, y)) == 1u {
+                sum += px(p, vec2<i32>(x, y));
+                count += 1.0;
+            }
+        }
+    }
+    return sum / max(count, 1.0);
+}
+
+// The candidate at p + offset along d, with red and blue recovered from the
+// color differences of its 3x3 neighborhood, which holds all three colors
+// everywhere but at the mirrored image edges
+fn xtrans_rgb(
+    p: vec2<i32>,
+    offset: vec2<i32>,
+    d: vec2<i32>,
+    greens: ptr<function, array<f32, 49>>,
+) -> vec3<f32> {
+    let color = cfa_color(p + offset);
+    let g = (*greens)[xtrans_index(offset)];
+    var sum = vec3<f32>(0.0);
+    var weight = vec3<f32>(0.0);
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let q = offset + vec2<i32>(x, y);
+            let c = cfa_color(p + q);
+            if c != 1u && (x != 0 || y != 0) {
+                let w = 1.0 / f32(x * x + y * y);
+                sum[c] += w * (px(p, q) - (*greens)[xtrans_index(q)]);
+                weight[c] += w;
+            }
+        }
+    }
+
+    var rgb = vec3<f32>(g);
+    for (var k = 0u; k < 3u; k += 2u) {
+        if weight[k] > 0.0 {
+            rgb[k] = g + sum[k] / weight[k];
+        } else if k != color {
+            rgb[k] = g + xtrans_edge_difference(p + offset, k, d);
+        }
+    }
+    rgb[color] = px(p, offset);
+    return max(rgb, vec3<f32>(0.0));
+}
+
+// The color difference from the 5x5 neighborhood, with the greens computed
+// on the spot since it is only needed at the edges
+fn xtrans_edge_difference(p: vec2<i32>, color: u32, d: vec2<i32>) -> f32 {
+    var sum = 0.0;
+    var weight = 0.0;
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let offset = vec2<i32>(x, y);
+            if cfa_color(p + offset) == color && (x != 0 || y != 0) {
+                let w = 1.0 / f32(x * x + y * y);
+                sum += w * (px(p, offset) - xtrans_green(mirror(p + offset), d));
+                weight += w;
+            }
+        }
+    }
+    return sum / max(weight, 1e-6);
+}
+
+fn demosaic(coords: vec2<i32>) -> vec4<f32> {
+    let p = coords;
+    var directions = xtrans_directions();
+    var candidates: array<vec3<f32>, 4>;
+    // Squared second derivatives in CIELab of each direction's candidates
+    // along that direction, for the 3x3 neighborhood of p
+    var derivatives: array<array<f32, 9>, 4>;
+
+    for (var i = 0; i < 4; i++) {
+        let d = directions[i];
+        var greens: array<f32, 49>;
+        for (var j = 0; j < XTRANS_WIDTH * XTRANS_WIDTH; j++) {
+            let offset = vec2<i32>(j % XTRANS_WIDTH, j / XTRANS_WIDTH) - XTRANS_REACH;
+            greens[j] = xtrans_green(mirror(p + offset), d);
+        }
+        // Every 3x3 neighbor and the ones next to it along d lie in the 5x5
+        var labs: array<vec3<f32>, 25>;
+        for (var j = 0; j < 25; j++) {
+            let offset = vec2<i32>(j % 5, j / 5) - 2;
+            let rgb = xtrans_rgb(p, offset, d, &greens);
+            if j == 12 {
+                candidates[i] = rgb;
+            }
+            labs[j] = to_lab(rgb);
+        }
+        for (var j = 0; j < 9; j++) {
+            let c = vec2<i32>(j % 3, j / 3) + 1;
+            let before = c - d;
+            let after = c + d;
+            let laplacian = 2.0 * labs[c.y * 5 + c.x] - labs[before.y * 5 + before.x]
+                - labs[after.y * 5 + after.x];
+            derivatives[i][j] = dot(laplacian, laplacian);
+        }
+    }
+
+    // A neighbor is homogeneous when it changes no more than eight times as
+    // much as the smoothest direction does at p
+    var threshold = derivatives[0][4];
+    for (var i = 1; i < 4; i++) {
+        threshold = min(threshold, derivatives[i][4]);
+    }
+    threshold *= 8.0;
+
+    // Markesteijn also sums the map over a 5x5 window, which would need a
+    // second pass, so the 3x3 counts at p pick the direction
+    var homogeneity: array<i32, 4>;
+    var best = 0;
+    for (var i = 0; i < 4; i++) {
+        var count = 0;
+        for (var j = 0; j < 9; j++) {
+            if derivatives[i][j] <= threshold {
+                count += 1;
+            }
+        }
+        homogeneity[i] = count;
+        best = max(best, count);
+    }
+
+    var rgb = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var i = 0; i < 4; i++) {
+        if homogeneity[i] >= best - best / 8 {
+            rgb += candidates[i];
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(rgb / count, 1.0);
+}
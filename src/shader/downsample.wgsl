@@ -6,26 +6,6 @@ var image: texture_2d<f32>;
 @binding(1)
 var output: texture_storage_2d<rgba32float, write>;
 
-struct Uniforms {
-    cam_2_xyz: mat3x4<f32>,
-    xyz_2_srgb: mat3x3<f32>,
-    whitelevels: vec4<f32>,
-    blacklevels: vec4<f32>,
-    crops: vec4<u32>,
-    mouse_pos: vec2<f32>,
-    window_size: vec2<f32>,
-    image_size: vec2<f32>,
-    output_size: vec2<f32>,
-    scroll_delta: f32,
-    exposure: f32,
-    contrast: f32,
-    // _padding: vec3<f32>,
-};
-
-@group(1)
-@binding(0)
-var<uniform> uniforms: Uniforms;
-
 @compute
 @workgroup_size(16, 16)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
@@ -35,8 +15,9 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    let normalized = vec2<f32>(coords) / vec2<f32>(uniforms.output_size);
-    let input_coords = cropped_coords(normalized);
+    // Sample at pixel centers so flipped axes stay inside the image
+    let normalized = (vec2<f32>(coords) + 0.5) / vec2<f32>(uniforms.output_size);
+    let input_coords = cropped_coords(orient(normalized));
 
     let color = textureLoad(image, input_coords, 0);
     textureStore(output, coords, color);
@@ -51,4 +32,19 @@ fn cropped_coords(normalized: vec2<f32>) -> vec2<i32> {
     let sample_x = normalized.x * (uniforms.image_size.x - right - left) + left;
     let sample_y = normalized.y * (uniforms.image_size.y - top - bottom) + top;
     return vec2<i32>(i32(sample_x), i32(sample_y));
-}
\ No newline at end of file
+}
+
+// Maps normalized display coordinates to normalized coordinates of the
+// stored image, undoing the EXIF orientation
+fn orient(uv: vec2<f32>) -> vec2<f32> {
+    switch uniforms.orientation {
+        case 2u: { return vec2<f32>(1.0 - uv.x, uv.y); }
+        case 3u: { return vec2<f32>(1.0 - uv.x, 1.0 - uv.y); }
+        case 4u: { return vec2<f32>(uv.x, 1.0 - uv.y); }
+        case 5u: { return vec2<f32>(uv.y, uv.x); }
+        case 6u: { return vec2<f32>(uv.y, 1.0 - uv.x); }
+        case 7u: { return vec2<f32>(1.0 - uv.y, 1.0 - uv.x); }
+        case 8u: { return vec2<f32>(1.0 - uv.y, uv.x); }
+        default: { return uv; }
+    }
+}
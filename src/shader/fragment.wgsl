@@ -1,23 +1,3 @@
-struct Uniforms {
-    cam_2_xyz: mat3x4<f32>,
-    xyz_2_srgb: mat3x3<f32>,
-    whitelevels: vec4<f32>,
-    blacklevels: vec4<f32>,
-    crops: vec4<u32>,
-    mouse_pos: vec2<f32>,
-    window_size: vec2<f32>,
-    image_size: vec2<f32>,
-    output_size: vec2<f32>,
-    scroll_delta: f32,
-    exposure: f32,
-    contrast: f32,
-    // _padding: vec3<f32>,
-};
-
-@group(1)
-@binding(0)
-var<uniform> uniforms: Uniforms;
-
 @group(0)
 @binding(0)
 var image: texture_2d<f32>;
@@ -66,13 +46,39 @@ fn fs_main(input: FragInput) -> @location(0) vec4<f32> {
         // enlarge the area affected by the glow
         let mouse_uv = uniforms.mouse_pos / uniforms.window_size;
         coords = mix(coords, mouse_uv, 1.0 - (0.5 + glow * 0.5));
-        var color = textureSample(image, image_sampler, coords);
+        var color = to_display(textureSample(image, image_sampler, coords));
         // color.r = mix(1.0 - color.r, color.r, glow);
         // color.g = mix(1.0 - color.g, color.g, glow);
         // color.b = mix(1.0 - color.b, color.b, glow);
         return color;
     }
-    return textureSample(image, image_sampler, input.uv);
+    return to_display(textureSample(image, image_sampler, input.uv));
+}
+
+// The output texture holds the encoded output color space, which the sRGB
+// window shows after converting it. Colors outside of sRGB are clipped.
+fn to_display(color: vec4<f32>) -> vec4<f32> {
+    let linear = vec3<f32>(decode(color.r), decode(color.g), decode(color.b));
+    let srgb = clamp(uniforms.output_2_display * linear, vec3<f32>(0.0), vec3<f32>(1.0));
+    return vec4<f32>(encode_srgb(srgb.r), encode_srgb(srgb.g), encode_srgb(srgb.b), color.a);
+}
+
+// Inverts `transfer_channel` of the processing shader, see color::Transfer
+fn decode(v: f32) -> f32 {
+    let x = max(v, 0.0);
+    switch uniforms.transfer {
+        case 0u: { return x; }
+        case 2u: { return pow(x, 563.0 / 256.0); }
+        case 3u: {
+            return select(pow((x + 0.099296826) / 1.099296826, 1.0 / 0.45), x / 4.5, x < 0.081242858);
+        }
+        case 4u: { return select(pow(x, 1.8), x / 16.0, x < 16.0 / 512.0); }
+        default: { return select(pow((x + 0.055) / 1.055, 2.4), x / 12.92, x <= 0.04045); }
+    }
+}
+
+fn encode_srgb(v: f32) -> f32 {
+    return select(1.055 * pow(v, 1.0 / 2.4) - 0.055, 12.92 * v, v <= 0.0031308);
 }
 
 fn circle_sdf(p: vec2<f32>, center: vec2<f32>, radius: f32) -> f32 {
@@ -0,0 +1,106 @@
+@group(0)
+@binding(0)
+var image: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var output: texture_storage_2d<rgba32float, write>;
+
+// OKLCh hues of the sRGB colors each band is centred on, in degrees, see
+// hsl::BAND_HUES
+const HUE_BANDS: array<f32, 8> = array<f32, 8>(29.0, 53.0, 110.0, 142.0, 195.0, 264.0, 294.0, 328.0);
+
+// Adjusts the scene-linear XYZ written by the processing pass
+@compute
+@workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let coords = vec2<i32>(global_id.xy);
+    if coords.x >= i32(uniforms.output_size.x) || coords.y >= i32(uniforms.output_size.y) {
+        return;
+    }
+
+    let xyz = textureLoad(image, coords, 0).xyz;
+    textureStore(output, coords, vec4<f32>(adjust_colors(xyz), 1.0));
+}
+
+// Ottosson's matrices from XYZ to cone responses, and from their cube roots
+// to OKLab, as rows for `v * m` like the ACES ones
+const XYZ_TO_LMS: mat3x3<f32> = mat3x3<f32>(
+    0.8189330101, 0.3618667424, -0.1288597137,
+    0.0329845436, 0.9293118715, 0.0361456387,
+    0.0482003018, 0.2643662691, 0.6338517070
+);
+const LMS_TO_OKLAB: mat3x3<f32> = mat3x3<f32>(
+    0.2104542553, 0.7936177850, -0.0040720468,
+    1.9779984951, -2.4285922050, 0.4505937099,
+    0.0259040371, 0.7827717662, -0.8086757660
+);
+const OKLAB_TO_LMS: mat3x3<f32> = mat3x3<f32>(
+    1.0, 0.3963377774, 0.2158037573,
+    1.0, -0.1055613458, -0.0638541728,
+    1.0, -0.0894841775, -1.2914855480
+);
+const LMS_TO_XYZ: mat3x3<f32> = mat3x3<f32>(
+    1.2270138511, -0.5577999807, 0.2812561490,
+    -0.0405801784, 1.1122568696, -0.0716766787,
+    -0.0763812845, -0.4214819784, 1.5861632204
+);
+// Below this chroma the hue is mostly noise, so the bands fade out
+const NEUTRAL_CHROMA: f32 = 0.02;
+// Colors with at least this chroma count as saturated for the vibrance
+const VIBRANT_CHROMA: f32 = 0.25;
+
+// The HSL bands along with the global saturation and vibrance. They work
+// in OKLCh, where the hue and chroma can change without the color looking
+// lighter or darker.
+fn adjust_colors(xyz: vec3<f32>) -> vec3<f32> {
+    let lab = xyz_to_oklab(xyz);
+    var chroma = length(lab.yz);
+    var hue = degrees(atan2(lab.z, lab.y));
+    let band = hue_band(hue);
+    let strength = smoothstep(0.0, NEUTRAL_CHROMA, chroma);
+
+    hue = radians(hue + band.x * strength);
+    // OKLab lightness follows the cube root of luminance
+    let lightness = lab.x * exp2(band.z * strength / 3.0);
+    chroma *= max(1.0 + band.y, 0.0) * max(1.0 + uniforms.saturation, 0.0);
+    let muted = 1.0 - smoothstep(0.0, VIBRANT_CHROMA, chroma);
+    chroma *= max(1.0 + uniforms.vibrance * muted, 0.0);
+
+    return oklab_to_xyz(vec3<f32>(lightness, chroma * cos(hue), chroma * sin(hue)));
+}
+
+// The adjustments of the two bands around the hue, blended by how close
+// it is to each
+fn hue_band(hue: f32) -> vec3<f32> {
+    let h = hue - floor(hue / 360.0) * 360.0;
+    var bands = HUE_BANDS;
+    // Hues past the last band or before the first lie between magenta and red
+    var low = 7u;
+    for (var i = 0u; i < 8u; i++) {
+        if bands[i] <= h {
+            low = i;
+        }
+    }
+    let high = (low + 1u) % 8u;
+    let width = bands[high] - bands[low] + select(0.0, 360.0, high == 0u);
+    let offset = h - bands[low] + select(0.0, 360.0, h < bands[low]);
+    let t = smoothstep(0.0, 1.0, offset / width);
+    return mix(band_adjustment(low), band_adjustment(high), t);
+}
+
+fn band_adjustment(band: u32) -> vec3<f32> {
+    let i = band / 4u;
+    let j = band % 4u;
+    return vec3<f32>(uniforms.hsl_hue[i][j], uniforms.hsl_saturation[i][j], uniforms.hsl_luminance[i][j]);
+}
+
+fn xyz_to_oklab(xyz: vec3<f32>) -> vec3<f32> {
+    let lms = xyz * XYZ_TO_LMS;
+    return (sign(lms) * pow(abs(lms), vec3<f32>(1.0 / 3.0))) * LMS_TO_OKLAB;
+}
+
+fn oklab_to_xyz(lab: vec3<f32>) -> vec3<f32> {
+    let lms = lab * OKLAB_TO_LMS;
+    return (lms * lms * lms) * LMS_TO_XYZ;
+}
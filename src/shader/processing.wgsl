@@ -6,25 +6,15 @@ var image: texture_2d<f32>;
 @binding(1)
 var output: texture_storage_2d<rgba32float, write>;
 
-struct Uniforms {
-    cam_2_xyz: mat3x4<f32>,
-    xyz_2_srgb: mat3x3<f32>,
-    whitelevels: vec4<f32>,
-    blacklevels: vec4<f32>,
-    crops: vec4<u32>,
-    mouse_pos: vec2<f32>,
-    window_size: vec2<f32>,
-    image_size: vec2<f32>,
-    output_size: vec2<f32>,
-    scroll_delta: f32,
-    exposure: f32,
-    contrast: f32,
-    // _padding: vec3<f32>,
-};
-
-@group(1)
-@binding(0)
-var<uniform> uniforms: Uniforms;
+// the red, green and blue curves, with the luminance curve in alpha
+@group(0)
+@binding(3)
+var curve_lut: texture_2d<f32>;
+
+// the 3D LUT, with red along x, green along y and blue along z
+@group(0)
+@binding(4)
+var lut: texture_3d<f32>;
 
 
 @compute
@@ -38,23 +28,85 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     }
 
     var color = textureLoad(image, coords, 0);
-    if uniforms.xyz_2_srgb[0].x == 1.0 {
-        textureStore(output, coords, color);
-        return;
+    if uniforms.input_kind == INPUT_SRGB {
+        color = vec4<f32>(srgb_to_linear(color.rgb), color.a);
     }
+    // The demosaic pass leaves the clip mask of raws in alpha
+    let clipped = (vec3<u32>(u32(color.a)) & vec3<u32>(1u, 2u, 4u)) != vec3<u32>(0u);
+    // The alpha channel would otherwise be mixed in by cam_2_xyz
+    color.a = 0.0;
 
     color = clamp(color, vec4<f32>(0.0), uniforms.whitelevels);
     color -= uniforms.blacklevels;
     color = max(color, vec4<f32>(0.0));
+    color *= uniforms.wb_multipliers;
+    if uniforms.input_kind == INPUT_RAW {
+        color = vec4<f32>(recover_highlights(color.rgb, clipped), color.a);
+    }
     var xyz = color.rgba * uniforms.cam_2_xyz;
     xyz *= pow(2.0, uniforms.exposure);
     xyz = contrast(xyz, uniforms.contrast);
 
-    var srgb_linear = uniforms.xyz_2_srgb * xyz.rgb;
-    let srgb_gamma = gamma(srgb_linear);
+    // The HSL pass adjusts these before `encode` picks them up again
+    textureStore(output, coords, vec4<f32>(xyz, 1.0));
+}
+
+// Turns the scene-linear XYZ of the HSL pass into output values
+@compute
+@workgroup_size(16, 16)
+fn encode(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let coords = vec2<i32>(global_id.xy);
+    if coords.x >= i32(uniforms.output_size.x) || coords.y >= i32(uniforms.output_size.y) {
+        return;
+    }
+
+    let xyz = textureLoad(image, coords, 0).xyz;
+    let rgb_linear = tone_map(uniforms.xyz_2_output * xyz, xyz.y);
+    let rgb = apply_curves(apply_lut(transfer(rgb_linear)));
+
+    textureStore(output, coords, vec4<f32>(rgb, 1.0));
+}
 
-    textureStore(output, coords, vec4<f32>(srgb_gamma, 1.0));
-    // textureStore(output, coords, color);
+// Each channel clips at its own level once white balanced. Left alone, the
+// channels that clip later tint blown highlights. `clipped` marks the
+// channels whose sensor values clipped around the pixel, as interpolated
+// channels can end up below the clip level there.
+fn recover_highlights(v: vec3<f32>, clipped: vec3<bool>) -> vec3<f32> {
+    let clip = ((uniforms.whitelevels - uniforms.blacklevels) * uniforms.wb_multipliers).rgb;
+    let at_clip = clipped | (v >= clip * 0.99);
+    switch uniforms.highlight_recovery {
+        case HIGHLIGHT_BLEND: { return blend_highlights(v, clip, at_clip); }
+        case HIGHLIGHT_RECONSTRUCT: { return reconstruct_highlights(v, clip, at_clip); }
+        default: { return clip_highlights(v, clip); }
+    }
+}
+
+fn clip_highlights(v: vec3<f32>, clip: vec3<f32>) -> vec3<f32> {
+    return min(v, vec3<f32>(min(min(clip.r, clip.g), clip.b)));
+}
+
+// Fades from the unclipped color to the neutral clipped one over the last
+// fifth below the clip level of the channel closest to clipping
+fn blend_highlights(v: vec3<f32>, clip: vec3<f32>, clipped: vec3<bool>) -> vec3<f32> {
+    let ratio = v / clip;
+    let t = select(smoothstep(0.8, 1.0, max(max(ratio.r, ratio.g), ratio.b)), 1.0, any(clipped));
+    return mix(v, clip_highlights(v, clip), t);
+}
+
+// Assumes clipped channels are at least as bright as the mean of the
+// unclipped ones, which is neutral in white balanced values. Where every
+// channel clipped, the brightest one is all that is left.
+fn reconstruct_highlights(v: vec3<f32>, clip: vec3<f32>, clipped: vec3<bool>) -> vec3<f32> {
+    let unclipped = select(vec3<f32>(1.0), vec3<f32>(0.0), clipped);
+    let count = dot(unclipped, vec3<f32>(1.0));
+    if count == 3.0 {
+        return v;
+    }
+    if count == 0.0 {
+        return vec3<f32>(max(max(v.r, v.g), v.b));
+    }
+    let estimate = dot(v, unclipped) / count;
+    return select(v, max(v, vec3<f32>(estimate)), clipped);
 }
 
 fn contrast(v: vec3<f32>, value: f32) -> vec3<f32> {
@@ -71,18 +123,200 @@ fn map_contrast(channel: f32, value: f32) -> f32 {
 }
 
 
-fn gamma(v: vec3<f32>) -> vec3<f32> {
+// Compresses scene-linear values into the range the output can encode
+fn tone_map(v: vec3<f32>, luminance: f32) -> vec3<f32> {
+    switch uniforms.tone_mapping {
+        case TONE_REINHARD: { return v / (1.0 + max(luminance, 0.0)); }
+        case TONE_FILMIC: { return filmic(v); }
+        case TONE_ACES: { return aces(v); }
+        default: { return v; }
+    }
+}
+
+// Scales the input like Hable's reference implementation, so that mid grey
+// doesn't end up too dark
+const FILMIC_EXPOSURE_BIAS: f32 = 2.0;
+
+fn filmic(v: vec3<f32>) -> vec3<f32> {
+    let black = uniforms.filmic_black;
+    let white = max(uniforms.filmic_white - black, 1e-3);
+    let x = max(v - black, vec3<f32>(0.0));
+    return hable(FILMIC_EXPOSURE_BIAS * x) / hable(vec3<f32>(FILMIC_EXPOSURE_BIAS * white));
+}
+
+fn hable(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+// The rows of Hill's matrices, which `v * m` multiplies with. They assume
+// linear sRGB, which the other output color spaces only approximate.
+const ACES_INPUT: mat3x3<f32> = mat3x3<f32>(
+    0.59719, 0.35458, 0.04823,
+    0.07600, 0.90834, 0.01566,
+    0.02840, 0.13383, 0.83777
+);
+const ACES_OUTPUT: mat3x3<f32> = mat3x3<f32>(
+    1.60475, -0.53108, -0.07367,
+    -0.10208, 1.10813, -0.00605,
+    -0.00327, -0.07276, 1.07602
+);
+
+fn aces(v: vec3<f32>) -> vec3<f32> {
+    let x = v * ACES_INPUT;
+    let a = x * (x + 0.0245786) - 0.000090537;
+    let b = x * (0.983729 * x + 0.4329510) + 0.238081;
+    return clamp((a / b) * ACES_OUTPUT, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Like the curves, LUTs are made for encoded values
+fn apply_lut(v: vec3<f32>) -> vec3<f32> {
+    if uniforms.lut_size == 0u {
+        return v;
+    }
+    let domain = uniforms.lut_domain_max - uniforms.lut_domain_min;
+    let normalized = clamp((v - uniforms.lut_domain_min) / domain, vec3<f32>(0.0), vec3<f32>(1.0));
+    let position = normalized * f32(uniforms.lut_size - 1u);
+    var graded: vec3<f32>;
+    if uniforms.lut_interpolation == LUT_TRILINEAR {
+        graded = trilinear(position);
+    } else {
+        graded = tetrahedral(position);
+    }
+    return mix(v, graded, uniforms.lut_intensity);
+}
+
+fn lut_entry(index: vec3<u32>) -> vec3<f32> {
+    return textureLoad(lut, min(index, vec3<u32>(uniforms.lut_size - 1u)), 0).rgb;
+}
+
+fn trilinear(position: vec3<f32>) -> vec3<f32> {
+    let base = vec3<u32>(position);
+    let f = fract(position);
+    let c00 = mix(lut_entry(base), lut_entry(base + vec3<u32>(1u, 0u, 0u)), f.x);
+    let c10 = mix(lut_entry(base + vec3<u32>(0u, 1u, 0u)), lut_entry(base + vec3<u32>(1u, 1u, 0u)), f.x);
+    let c01 = mix(lut_entry(base + vec3<u32>(0u, 0u, 1u)), lut_entry(base + vec3<u32>(1u, 0u, 1u)), f.x);
+    let c11 = mix(lut_entry(base + vec3<u32>(0u, 1u, 1u)), lut_entry(base + vec3<u32>(1u, 1u, 1u)), f.x);
+    return mix(mix(c00, c10, f.y), mix(c01, c11, f.y), f.z);
+}
+
+// Splits the cube between the entries into six tetrahedra along its
+// diagonal, and blends the corners of the one the position falls into.
+// Grays stay on the diagonal, so they only mix gray entries.
+fn tetrahedral(position: vec3<f32>) -> vec3<f32> {
+    let base = vec3<u32>(position);
+    let f = fract(position);
+    let c000 = lut_entry(base);
+    let c111 = lut_entry(base + vec3<u32>(1u));
+    // The axes ordered by the fraction along them, largest first
+    var first: vec3<u32>;
+    var second: vec3<u32>;
+    var weights: vec3<f32>;
+    if f.x >= f.y && f.y >= f.z {
+        first = vec3<u32>(1u, 0u, 0u);
+        second = vec3<u32>(1u, 1u, 0u);
+        weights = f.xyz;
+    } else if f.x >= f.z && f.z >= f.y {
+        first = vec3<u32>(1u, 0u, 0u);
+        second = vec3<u32>(1u, 0u, 1u);
+        weights = f.xzy;
+    } else if f.z >= f.x && f.x >= f.y {
+        first = vec3<u32>(0u, 0u, 1u);
+        second = vec3<u32>(1u, 0u, 1u);
+        weights = f.zxy;
+    } else if f.y >= f.x && f.x >= f.z {
+        first = vec3<u32>(0u, 1u, 0u);
+        second = vec3<u32>(1u, 1u, 0u);
+        weights = f.yxz;
+    } else if f.y >= f.z && f.z >= f.x {
+        first = vec3<u32>(0u, 1u, 0u);
+        second = vec3<u32>(0u, 1u, 1u);
+        weights = f.yzx;
+    } else {
+        first = vec3<u32>(0u, 0u, 1u);
+        second = vec3<u32>(0u, 1u, 1u);
+        weights = f.zyx;
+    }
+    let c1 = lut_entry(base + first);
+    let c2 = lut_entry(base + second);
+    return (1.0 - weights.x) * c000
+        + (weights.x - weights.y) * c1
+        + (weights.y - weights.z) * c2
+        + weights.z * c111;
+}
+
+// The curves work on encoded values, like the ones they are drawn over
+fn apply_curves(v: vec3<f32>) -> vec3<f32> {
+    let rgb = vec3<f32>(curve(v.r).r, curve(v.g).g, curve(v.b).b);
+    // Rec. 709 luma, scaled so that the color keeps its hue
+    let luma = dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let mapped = curve(luma).a;
+    if luma < 1e-5 {
+        return vec3<f32>(mapped);
+    }
+    return clamp(rgb * (mapped / luma), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Linearly interpolates between the LUT entries around the value
+fn curve(v: f32) -> vec4<f32> {
+    let last = textureDimensions(curve_lut).x - 1u;
+    let position = clamp(v, 0.0, 1.0) * f32(last);
+    let index = u32(position);
+    let low = textureLoad(curve_lut, vec2<u32>(index, 0u), 0);
+    let high = textureLoad(curve_lut, vec2<u32>(min(index + 1u, last), 0u), 0);
+    return mix(low, high, fract(position));
+}
+
+// Encodes linear values with the curve of the output color space
+fn transfer(v: vec3<f32>) -> vec3<f32> {
     return vec3<f32>(
-        gamma_correct(v.r),
-        gamma_correct(v.g),
-        gamma_correct(v.b)
+        transfer_channel(v.r),
+        transfer_channel(v.g),
+        transfer_channel(v.b)
     );
 }
 
+fn transfer_channel(v: f32) -> f32 {
+    switch uniforms.transfer {
+        case 0u: { return v; }
+        case 2u: { return pow(max(v, 0.0), 256.0 / 563.0); }
+        case 3u: { return rec709(v); }
+        case 4u: { return prophoto(v); }
+        default: { return gamma_correct(v); }
+    }
+}
+
+fn srgb_to_linear(v: vec3<f32>) -> vec3<f32> {
+    let low = v / 12.92;
+    let high = pow((max(v, vec3<f32>(0.0)) + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, v <= vec3<f32>(0.04045));
+}
+
 fn gamma_correct(v: f32) -> f32 {
     if v <= 0.0031308 {
         return 12.92 * v;
     } else {
         return 1.055 * pow(v, 1.0 / 2.4) - 0.055;
     }
-}
\ No newline at end of file
+}
+
+fn rec709(v: f32) -> f32 {
+    if v < 0.018053968 {
+        return 4.5 * v;
+    } else {
+        return 1.099296826 * pow(v, 0.45) - 0.099296826;
+    }
+}
+
+fn prophoto(v: f32) -> f32 {
+    if v < 1.0 / 512.0 {
+        return 16.0 * v;
+    } else {
+        return pow(v, 1.0 / 1.8);
+    }
+}
@@ -1,6 +1,8 @@
 struct Uniforms {
     cam_2_xyz: mat3x4<f32>,
-    xyz_2_srgb: mat3x3<f32>,
+    xyz_2_output: mat3x3<f32>,
+    // linear output RGB to linear sRGB, for showing it in the window
+    output_2_display: mat3x3<f32>,
     whitelevels: vec4<f32>,
     blacklevels: vec4<f32>,
     crops: vec4<u32>,
@@ -9,9 +11,69 @@ struct Uniforms {
     image_size: vec2<f32>,
     output_size: vec2<f32>,
     scroll_delta: f32,
-    _padding: vec3<f32>,
+    exposure: f32,
+    contrast: f32,
+    // repeat period of the CFA, 2 for Bayer and 6 for X-Trans sensors
+    cfa_size: u32,
+    // color index (0 = R, 1 = G, 2 = B) of the CFA tiled to 6x6, row-major
+    cfa: array<vec4<u32>, 9>,
+    // EXIF orientation of the stored pixels
+    orientation: u32,
+    // white balance gains of the camera channels
+    wb_multipliers: vec4<f32>,
+    // transfer curve of the output color space, see color::Transfer
+    transfer: u32,
+    // one of the INPUT_ constants
+    input_kind: u32,
+    // one of the HIGHLIGHT_ constants
+    highlight_recovery: u32,
+    // one of the TONE_ constants
+    tone_mapping: u32,
+    // scene-linear values the filmic curve maps to white and black
+    filmic_white: f32,
+    filmic_black: f32,
+    // how much of the 3D LUT is mixed in, from 0 to 1
+    lut_intensity: f32,
+    // one of the LUT_ constants
+    lut_interpolation: u32,
+    // the inputs that map to the first and last entries of the 3D LUT
+    lut_domain_min: vec3<f32>,
+    // entries along each axis of the 3D LUT, 0 without one
+    lut_size: u32,
+    lut_domain_max: vec3<f32>,
+    // per hue band, in the order of the HUE_BANDS: the hue shift in
+    // degrees, the change in chroma and the change in luminance in stops
+    hsl_hue: array<vec4<f32>, 2>,
+    hsl_saturation: array<vec4<f32>, 2>,
+    hsl_luminance: array<vec4<f32>, 2>,
+    // global change in chroma, and in the chroma of muted colors
+    saturation: f32,
+    vibrance: f32,
 };
 
+// sensor data that still has to be demosaiced
+const INPUT_RAW: u32 = 0u;
+// sRGB encoded color, such as JPEGs and the previews embedded in raws
+const INPUT_SRGB: u32 = 1u;
+// linear float color, such as from EXR files, which isn't clipped at 1
+const INPUT_LINEAR: u32 = 2u;
+
+// see compute::processing::HighlightRecovery
+const HIGHLIGHT_CLIP: u32 = 0u;
+const HIGHLIGHT_BLEND: u32 = 1u;
+const HIGHLIGHT_RECONSTRUCT: u32 = 2u;
+
+// see compute::processing::ToneMapping
+const TONE_NONE: u32 = 0u;
+const TONE_REINHARD: u32 = 1u;
+const TONE_FILMIC: u32 = 2u;
+const TONE_ACES: u32 = 3u;
+
+// see compute::processing::LutInterpolation
+const LUT_TRILINEAR: u32 = 0u;
+const LUT_TETRAHEDRAL: u32 = 1u;
+
 @group(1)
 @binding(0)
-var<uniform> uniforms: Uniforms;
\ No newline at end of file
+var<uniform> uniforms: Uniforms;
+
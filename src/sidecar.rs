@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+
+use crate::settings::Settings;
+
+/// Version of the sidecar layout written by this build
+pub const VERSION: i64 = 1;
+
+/// The edits of an image, stored in a TOML file next to it. The whole
+/// document is kept, so fields written by a newer version survive a save.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sidecar {
+    document: toml::Table,
+}
+
+impl Sidecar {
+    /// `IMG_0042.CR2` keeps its edits in `IMG_0042.CR2.toml`
+    pub fn path_for(image: &Path) -> PathBuf {
+        let mut path = image.as_os_str().to_owned();
+        path.push(".toml");
+        PathBuf::from(path)
+    }
+
+    /// Reads the sidecar of an image, if it has one.
+    pub fn load(image: &Path) -> crate::Result<Option<Self>> {
+        let path = Self::path_for(image);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Self::parse(&std::fs::read_to_string(path)?).map(Some)
+    }
+
+    pub fn save(&self, image: &Path) -> crate::Result<()> {
+        std::fs::write(
+            Self::path_for(image),
+            toml::to_string_pretty(&self.document)?,
+        )?;
+        Ok(())
+    }
+
+    fn parse(text: &str) -> crate::Result<Self> {
+        Ok(Self {
+            document: toml::from_str(text)?,
+        })
+    }
+
+    pub fn version(&self) -> i64 {
+        self.document
+            .get("version")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(VERSION)
+    }
+
+    /// The stored settings, with defaults for anything that isn't stored or
+    /// that this build can't read
+    pub fn settings(&self) -> crate::Result<Settings> {
+        self.read_settings().map(|(settings, _)| settings)
+    }
+
+    /// Reads the stored settings one field at a time, so a value written by
+    /// a newer version only loses that field. Also returns the keys that
+    /// couldn't be read.
+    fn read_settings(&self) -> crate::Result<(Settings, Vec<String>)> {
+        let stored = match self.document.get("settings") {
+            Some(toml::Value::Table(stored)) => stored,
+            Some(_) => return Err("The settings in the sidecar aren't a table".into()),
+            None => return Ok((Settings::default(), Vec::new())),
+        };
+        let mut readable = toml::Table::new();
+        let mut unreadable = Vec::new();
+        for (key, value) in stored {
+            let field: toml::Table = std::iter::once((key.clone(), value.clone())).collect();
+            if toml::Value::Table(field).try_into::<Settings>().is_ok() {
+                readable.insert(key.clone(), value.clone());
+            } else {
+                unreadable.push(key.clone());
+            }
+        }
+        Ok((toml::Value::Table(readable).try_into()?, unreadable))
+    }
+
+    /// Overwrites the fields we know about, leaving any others in place.
+    /// Stored values that this build can't read are kept unless the field
+    /// was changed.
+    pub fn set_settings(&mut self, settings: &Settings) -> crate::Result<()> {
+        let toml::Value::Table(values) = toml::Value::try_from(settings)? else {
+            return Err("Settings must serialise to a table".into());
+        };
+        let (current, unreadable) = self.read_settings()?;
+        let toml::Value::Table(current) = toml::Value::try_from(current)? else {
+            return Err("Settings must serialise to a table".into());
+        };
+        let changed = values
+            .into_iter()
+            .filter(|(key, value)| !unreadable.contains(key) || current.get(key) != Some(value));
+        match self.document.get_mut("settings") {
+            Some(toml::Value::Table(table)) => table.extend(changed),
+            _ => {
+                self.document.insert(
+                    "settings".to_string(),
+                    toml::Value::Table(changed.collect()),
+                );
+            }
+        }
+        let version = self.version().max(VERSION);
+        self.document
+            .insert("version".to_string(), toml::Value::Integer(version));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::cognitive_complexity)]
+mod tests {
+    use super::*;
+    use crate::compute::processing::ToneMapping;
+
+    #[test]
+    fn test_keeps_unknown_fields() {
+        let mut sidecar = Sidecar::parse(
+            "version = 3\nrating = 4\n\n[settings]\nexposure = 0.5\nvignette = 0.2\n",
+        )
+        .unwrap();
+        let mut settings = sidecar.settings().unwrap();
+        assert!((settings.exposure - 0.5).abs() < f32::EPSILON);
+
+        settings.exposure = 1.0;
+        sidecar.set_settings(&settings).unwrap();
+        let saved = Sidecar::parse(&toml::to_string_pretty(&sidecar.document).unwrap()).unwrap();
+        assert_eq!(saved.version(), 3);
+        assert_eq!(saved.settings().unwrap(), settings);
+        assert_eq!(saved.document["rating"].as_integer(), Some(4));
+        assert_eq!(saved.document["settings"]["vignette"].as_float(), Some(0.2));
+    }
+
+    #[test]
+    fn test_keeps_values_from_newer_versions() {
+        let mut sidecar = Sidecar::parse(
+            "[settings]\nexposure = 0.5\ntone_mapping = \"agx\"\ncontrast = \"high\"\n",
+        )
+        .unwrap();
+        let mut settings = sidecar.settings().unwrap();
+        assert!((settings.exposure - 0.5).abs() < f32::EPSILON);
+        assert_eq!(settings.tone_mapping, ToneMapping::None);
+
+        settings.exposure = 1.0;
+        settings.contrast = 1.5;
+        sidecar.set_settings(&settings).unwrap();
+        let saved = &sidecar.document["settings"];
+        assert_eq!(saved["exposure"].as_float(), Some(1.0));
+        assert_eq!(saved["tone_mapping"].as_str(), Some("agx"));
+        // Changing a field we couldn't read replaces it
+        assert_eq!(saved["contrast"].as_float(), Some(1.5));
+    }
+
+    #[test]
+    fn test_removed_lut_is_saved() {
+        let mut sidecar = Sidecar::parse("[settings]\nlut = \"grades/film.cube\"\n").unwrap();
+        let mut settings = sidecar.settings().unwrap();
+        assert_eq!(settings.lut.as_deref(), Some(Path::new("grades/film.cube")));
+
+        settings.lut = None;
+        sidecar.set_settings(&settings).unwrap();
+        let saved = Sidecar::parse(&toml::to_string_pretty(&sidecar.document).unwrap()).unwrap();
+        assert_eq!(saved.settings().unwrap().lut, None);
+    }
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(
+            Sidecar::path_for(Path::new("shoot/IMG_0042.CR2")),
+            Path::new("shoot/IMG_0042.CR2.toml")
+        );
+    }
+}
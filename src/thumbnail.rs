@@ -0,0 +1,216 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use iced::{
+    futures::{Stream, channel::mpsc},
+    widget::image::Handle,
+};
+use tracing::warn;
+
+use crate::{
+    cache, color::OutputColorSpace, headless::Headless, loader, program::Program, sidecar::Sidecar,
+};
+
+/// Longest side of a thumbnail, in pixels
+pub const SIZE: u32 = 160;
+/// The disk cache is pruned to this size, which holds a few thousand
+/// thumbnails
+const MAX_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Renders the thumbnails one after another on a background thread, reading
+/// them from the disk cache where possible. Dropping the stream stops it
+/// after the current file.
+#[allow(clippy::cognitive_complexity)]
+pub fn generate(paths: Vec<PathBuf>) -> impl Stream<Item = (PathBuf, Handle)> {
+    let (sender, receiver) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        if let Ok(dir) = cache_dir()
+            && let Err(e) = prune(&dir, MAX_CACHE_BYTES)
+        {
+            warn!("Could not prune the thumbnail cache: {e}");
+        }
+        // Only set up a GPU device once something isn't cached
+        let mut headless = None;
+        for path in paths {
+            if sender.is_closed() {
+                return;
+            }
+            match thumbnail(&path, &mut headless) {
+                Ok(handle) => {
+                    if sender.unbounded_send((path, handle)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => warn!("Could not create a thumbnail for {path:?}: {e}"),
+            }
+        }
+    });
+    receiver
+}
+
+#[allow(clippy::cognitive_complexity)]
+fn thumbnail(path: &Path, headless: &mut Option<Headless>) -> crate::Result<Handle> {
+    let cache_path = cache_path(path)?;
+    if cache_path.is_file() {
+        // Pruning removes the thumbnails that were used least recently
+        if let Err(e) = File::options()
+            .append(true)
+            .open(&cache_path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            warn!("Could not mark {cache_path:?} as used: {e}");
+        }
+        return Ok(Handle::from_path(cache_path));
+    }
+
+    let decoded = loader::decode_image(path)?;
+    let mut program = Program::default();
+    program.set_image(&decoded);
+    // The filmstrip shows the pixels as they are, which only works for sRGB
+    program.settings.output_color_space = OutputColorSpace::Srgb;
+    let size = crate::util::calculate_image_size(iced::Size::new(SIZE, SIZE), program.image_size);
+    if headless.is_none() {
+        *headless = Some(Headless::new()?);
+    }
+    let rendered = headless
+        .as_ref()
+        .ok_or("No GPU device")?
+        .render(&program, size)?;
+    let rgba = image::DynamicImage::ImageRgba32F(rendered).into_rgba8();
+
+    if let Some(dir) = cache_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if let Err(e) = rgba.save(&cache_path) {
+        warn!("Could not cache the thumbnail of {path:?}: {e}");
+    }
+    Ok(Handle::from_rgba(
+        rgba.width(),
+        rgba.height(),
+        rgba.into_raw(),
+    ))
+}
+
+fn cache_dir() -> crate::Result<PathBuf> {
+    let dir = dirs::cache_dir().ok_or("No cache directory")?;
+    Ok(dir.join(env!("CARGO_PKG_NAME")).join("thumbnails"))
+}
+
+/// Thumbnails are keyed by the file's path and the modification times of
+/// the file and its sidecar, so an edited file gets a new one.
+fn cache_path(path: &Path) -> crate::Result<PathBuf> {
+    let modified = std::fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?;
+    // Editing the image changes its thumbnail as well
+    let sidecar_modified = cache::modified(&Sidecar::path_for(path))
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or(Duration::ZERO);
+
+    let mut hasher = Fnv::new();
+    let path = std::fs::canonicalize(path)?;
+    let path = path.as_os_str().as_encoded_bytes();
+    hasher.write(&(path.len() as u64).to_le_bytes());
+    hasher.write(path);
+    for time in [modified, sidecar_modified] {
+        hasher.write(&time.as_secs().to_le_bytes());
+        hasher.write(&time.subsec_nanos().to_le_bytes());
+    }
+    hasher.write(&SIZE.to_le_bytes());
+    Ok(cache_dir()?.join(format!("{:016x}.png", hasher.finish())))
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, it hashes the same on every Rust
+/// release, so the cached thumbnails keep their names.
+struct Fnv(u64);
+
+impl Fnv {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    const fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    const fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Deletes the least recently used thumbnails until the rest fit in
+/// `max_bytes`.
+fn prune(dir: &Path, max_bytes: u64) -> crate::Result<()> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        // Nothing has been cached yet
+        return Ok(());
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(std::result::Result::ok)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(std::fs::Metadata::is_file)?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    // Newest first, so the oldest are popped off the end
+    files.sort_by_key(|&(modified, ..)| std::cmp::Reverse(modified));
+    while total > max_bytes
+        && let Some((_, len, path)) = files.pop()
+    {
+        std::fs::remove_file(&path)?;
+        total -= len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used, clippy::cognitive_complexity)]
+mod tests {
+    use super::*;
+
+    fn fnv(bytes: &[u8]) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.write(bytes);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_fnv() {
+        // The reference values of the FNV-1a test suite
+        assert_eq!(fnv(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_prune_removes_least_recently_used() {
+        let dir = std::env::temp_dir().join("wgpu_compute_test_thumbnails");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for (i, name) in ["b.png", "a.png", "c.png"].iter().enumerate() {
+            let file = File::create(dir.join(name)).unwrap();
+            file.set_len(100).unwrap();
+            file.set_modified(start + Duration::from_secs(i as u64))
+                .unwrap();
+        }
+
+        prune(&dir, 300).unwrap();
+        assert!(dir.join("b.png").is_file());
+        prune(&dir, 250).unwrap();
+        assert!(!dir.join("b.png").exists());
+        assert!(dir.join("a.png").is_file() && dir.join("c.png").is_file());
+        prune(&dir, 0).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
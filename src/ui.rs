@@ -1,84 +1,511 @@
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
-    time::Instant,
+    sync::Arc,
+    time::{Instant, SystemTime},
 };
 
-use iced::Element;
-use tracing::error;
+use iced::{Element, Task};
+use tracing::{debug, error, info};
 
-use crate::{program::Program, util::Tou32};
+use crate::{
+    cache::{self, ImageCache},
+    color::OutputColorSpace,
+    compute::{
+        demosaic::DemosaicAlgorithm,
+        processing::{self, HighlightRecovery, LutInterpolation, ToneMapping},
+    },
+    curve::{Curve, CurveChannel},
+    curve_editor::{self, CurveEditor},
+    export::{self, BitDepth, ExportFormat, ExportOptions, ExportSettings, Exported},
+    format,
+    headless::Headless,
+    hsl::{self, HueBand},
+    loader::{self, LoadEvent, LoadStage},
+    metadata::{self, Metadata},
+    program::{Decoded, Program},
+    sidecar::Sidecar,
+    thumbnail,
+    util::{Tof32, Tou32},
+    white_balance::{self, WhiteBalance},
+};
+
+/// Room below the image for the controls, filmstrip and footer
+const CHROME_HEIGHT: u32 = 320;
+/// Displayed size of the thumbnails, which are rendered at twice that to
+/// stay sharp on high DPI screens
+const FILMSTRIP_THUMBNAIL_SIZE: f32 = 80.0;
+const METADATA_PANEL_WIDTH: f32 = 300.0;
 
 #[derive(Default, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Ui {
     #[allow(dead_code)]
     program: Program,
     window_size: iced::Size,
+    error: Option<String>,
+    /// Feedback that isn't an error, like where an export went
+    notice: Option<String>,
+    loading: Option<Loading>,
+    cache: ImageCache,
+    /// The folder whose images are listed in the filmstrip
+    folder: Option<PathBuf>,
+    /// The supported images in `folder`, sorted by name
+    files: Vec<PathBuf>,
+    /// The modification time of `folder` when `files` were listed
+    folder_modified: Option<SystemTime>,
+    thumbnails: HashMap<PathBuf, iced::widget::image::Handle>,
+    /// Renders the thumbnails that are still missing
+    thumbnail_task: Option<iced::task::Handle>,
+    /// Decodes the neighbouring files into the cache, once there are any
+    prefetcher: Option<loader::Prefetcher>,
+    /// Whether a file is being dragged over the window
+    hovering: bool,
+    /// The sidecar of the current image, which the edits are saved to
+    sidecar: Sidecar,
+    export_settings: ExportSettings,
+    exporting: bool,
+    /// The GPU device of the last export, kept for the next one
+    export_device: Option<Arc<Headless>>,
+    metadata: Metadata,
+    show_metadata: bool,
+    /// Whether the next click on the image picks the white balance
+    picking_white: bool,
+    /// The curve shown in the curve editor
+    curve_channel: CurveChannel,
+    /// The band whose HSL sliders are shown
+    hue_band: HueBand,
+}
+
+/// An image that is being decoded in the background
+#[derive(Debug)]
+struct Loading {
+    path: PathBuf,
+    stage: LoadStage,
+    /// The fraction of the decode that is done
+    progress: f32,
+    handle: iced::task::Handle,
+    cancel: loader::Cancel,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
+    Open(PathBuf),
+    OpenFileDialog,
+    OpenFolderDialog,
+    Picked(Option<PathBuf>),
     LoadImage(PathBuf),
+    Loaded(LoadEvent),
+    Prefetched(PathBuf, Result<Arc<Decoded>, String>),
+    /// The listing of a folder, and whether to open its first image
+    FolderListed(PathBuf, Option<loader::Listing>, bool),
+    Thumbnail(PathBuf, iced::widget::image::Handle),
     UpdateImage,
     MouseMoved(iced::Point),
+    MousePressed,
     MouseScrolled(iced::mouse::ScrollDelta),
     WindowEvent(iced::window::Event),
     Exposure(f32),
     Contrast(f32),
+    DemosaicAlgorithm(DemosaicAlgorithm),
+    HighlightRecovery(HighlightRecovery),
+    ToneMapping(ToneMapping),
+    FilmicWhite(f32),
+    FilmicBlack(f32),
+    CurveChannel(CurveChannel),
+    Curve(Curve),
+    ResetCurve,
+    Saturation(f32),
+    Vibrance(f32),
+    HueBand(HueBand),
+    BandHue(f32),
+    BandSaturation(f32),
+    BandLuminance(f32),
+    LutDialog,
+    LutPicked(Option<PathBuf>),
+    RemoveLut,
+    LutIntensity(f32),
+    LutInterpolation(LutInterpolation),
+    OutputColorSpace(OutputColorSpace),
+    WhiteBalance(WhiteBalance),
+    Temperature(f32),
+    Tint(f32),
+    PickWhite,
+    SaveSidecar,
+    ShowMetadata(bool),
+    ExportDialog,
+    ExportPicked(Option<PathBuf>),
+    Exported(Exported),
+    ExportBitDepth(BitDepth),
+    JpegQuality(u8),
 }
 
 impl Ui {
+    pub fn new(cache: ImageCache) -> Self {
+        Self {
+            cache,
+            ..Default::default()
+        }
+    }
+
     pub fn view(&self) -> Element<'_, Message> {
-        if self.program.image_path.as_os_str().is_empty() {
-            iced::widget::text("No image loaded").into()
+        let content = if self.program.image_path.as_os_str().is_empty() {
+            self.empty_view()
         } else {
-            iced::widget::center(iced::widget::column![
+            let main = iced::widget::center(iced::widget::column![
                 self.image_view(),
                 self.control_view(),
+                self.filmstrip_view(),
                 self.footer_view()
             ])
-            .style(Self::style)
-            .into()
+            .style(Self::style);
+            if self.show_metadata {
+                iced::widget::row![main, self.metadata_view()].into()
+            } else {
+                main.into()
+            }
+        };
+        if self.hovering {
+            iced::widget::stack![content, Self::drop_overlay()].into()
+        } else {
+            content
         }
     }
 
-    fn image_view(&self) -> Element<'_, Message> {
-        let mut window_size = self.window_size.to_u32();
-        window_size.height -= 50;
-        let size = crate::util::calculate_image_size(window_size, self.program.image_size);
-        iced::widget::container(
-            iced::widget::mouse_area(
-                iced::widget::shader(self.program.clone())
-                    .height(size.height)
-                    .width(size.width),
-            )
-            .on_move(Message::MouseMoved)
-            .on_scroll(Message::MouseScrolled)
-            .on_exit(Message::UpdateImage),
+    fn drop_overlay<'a>() -> Element<'a, Message> {
+        iced::widget::center(
+            iced::widget::text("Drop to open")
+                .size(24)
+                .color(iced::Color::WHITE),
         )
-        .center_y(iced::Length::Fill)
-        .center_x(self.window_size.width)
+        .style(|_| iced::widget::container::Style {
+            background: Some(iced::Background::Color(iced::Color::from_rgba(
+                0.0, 0.0, 0.0, 0.6,
+            ))),
+            border: iced::Border {
+                width: 2.0,
+                color: iced::Color::WHITE,
+                radius: iced::border::Radius::new(20.0),
+            },
+            ..Default::default()
+        })
         .into()
     }
 
+    fn empty_view(&self) -> Element<'_, Message> {
+        let status = if self.loading.is_some() {
+            "Loading..."
+        } else {
+            "No image loaded"
+        };
+        iced::widget::center(
+            iced::widget::column![
+                iced::widget::text(status),
+                iced::widget::row![
+                    iced::widget::button("Open file...").on_press(Message::OpenFileDialog),
+                    iced::widget::button("Open folder...").on_press(Message::OpenFolderDialog),
+                ]
+                .spacing(10),
+                self.error_view(),
+            ]
+            .spacing(10)
+            .align_x(iced::Alignment::Center),
+        )
+        .into()
+    }
+
+    /// Width left for the image next to the metadata panel
+    fn image_area_width(&self) -> f32 {
+        if self.show_metadata {
+            (self.window_size.width - METADATA_PANEL_WIDTH).max(0.0)
+        } else {
+            self.window_size.width
+        }
+    }
+
+    /// Size of the image on screen, fitted into the space above the controls
+    fn image_display_size(&self) -> iced::Size<u32> {
+        let mut window_size =
+            iced::Size::new(self.image_area_width(), self.window_size.height).to_u32();
+        window_size.height = window_size.height.saturating_sub(CHROME_HEIGHT);
+        crate::util::calculate_image_size(window_size, self.program.image_size)
+    }
+
+    fn image_view(&self) -> Element<'_, Message> {
+        let size = self.image_display_size();
+        let mut image = iced::widget::mouse_area(
+            iced::widget::shader(self.program.clone())
+                .height(size.height)
+                .width(size.width),
+        )
+        .on_move(Message::MouseMoved)
+        .on_press(Message::MousePressed)
+        .on_scroll(Message::MouseScrolled)
+        .on_exit(Message::UpdateImage);
+        if self.picking_white {
+            image = image.interaction(iced::mouse::Interaction::Crosshair);
+        }
+        iced::widget::container(image)
+            .center_y(iced::Length::Fill)
+            .center_x(self.image_area_width())
+            .into()
+    }
+
+    fn metadata_section<'a>(
+        title: &'a str,
+        fields: &'a [metadata::Field],
+    ) -> iced::widget::Column<'a, Message> {
+        let rows = fields.iter().map(|field| {
+            iced::widget::row![
+                iced::widget::text(field.name).size(11).width(100),
+                iced::widget::text(&field.value).size(11),
+            ]
+            .spacing(6)
+            .into()
+        });
+        iced::widget::column![iced::widget::text(title).size(14)]
+            .extend(rows)
+            .spacing(4)
+    }
+
+    fn metadata_view(&self) -> Element<'_, Message> {
+        let mut sections = iced::widget::column![].spacing(16);
+        if !self.metadata.raw.is_empty() {
+            sections = sections.push(Self::metadata_section("Raw", &self.metadata.raw));
+        }
+        if !self.metadata.exif.is_empty() {
+            sections = sections.push(Self::metadata_section("EXIF", &self.metadata.exif));
+        }
+        if self.metadata.raw.is_empty() && self.metadata.exif.is_empty() {
+            sections = sections.push(iced::widget::text("No metadata").size(11));
+        }
+        iced::widget::container(iced::widget::scrollable(sections.padding(10)))
+            .width(METADATA_PANEL_WIDTH)
+            .height(iced::Length::Fill)
+            .style(iced::widget::container::bordered_box)
+            .into()
+    }
+
     pub fn control_view(&self) -> Element<'_, Message> {
         iced::widget::center_x(
             iced::widget::row![
-                iced::widget::slider(-3.0..=3.0, self.program.exposure, Message::Exposure)
-                    .step(0.01)
-                    .width(200),
-                iced::widget::slider(0.0..=3.0, self.program.contrast, Message::Contrast)
-                    .step(0.01)
-                    .width(200)
+                iced::widget::column![
+                    self.tone_controls(),
+                    self.tone_mapping_controls(),
+                    self.white_balance_controls(),
+                    self.color_controls(),
+                    self.lut_controls()
+                ]
+                .spacing(10)
+                .align_x(iced::Alignment::Center),
+                self.curve_controls()
             ]
             .spacing(20),
         )
         .into()
     }
 
+    fn tone_controls(&self) -> Element<'_, Message> {
+        iced::widget::row![
+            iced::widget::slider(
+                -3.0..=3.0,
+                self.program.settings.exposure,
+                Message::Exposure
+            )
+            .step(0.01)
+            .width(200)
+            .on_release(Message::SaveSidecar),
+            iced::widget::slider(0.0..=3.0, self.program.settings.contrast, Message::Contrast)
+                .step(0.01)
+                .width(200)
+                .on_release(Message::SaveSidecar),
+            iced::widget::pick_list(
+                DemosaicAlgorithm::ALL,
+                Some(self.program.settings.demosaic_algorithm),
+                Message::DemosaicAlgorithm
+            ),
+            iced::widget::pick_list(
+                HighlightRecovery::ALL,
+                Some(self.program.settings.highlight_recovery),
+                Message::HighlightRecovery
+            ),
+            iced::widget::pick_list(
+                OutputColorSpace::ALL,
+                Some(self.program.settings.output_color_space),
+                Message::OutputColorSpace
+            )
+        ]
+        .spacing(20)
+        .into()
+    }
+
+    fn tone_mapping_controls(&self) -> Element<'_, Message> {
+        let settings = &self.program.settings;
+        let mut row = iced::widget::row![iced::widget::pick_list(
+            ToneMapping::ALL,
+            Some(settings.tone_mapping),
+            Message::ToneMapping
+        )]
+        .spacing(20);
+        if settings.tone_mapping == ToneMapping::Filmic {
+            row = row
+                .push(
+                    iced::widget::slider(
+                        processing::FILMIC_WHITE_RANGE,
+                        settings.filmic_white,
+                        Message::FilmicWhite,
+                    )
+                    .step(0.1)
+                    .width(200)
+                    .on_release(Message::SaveSidecar),
+                )
+                .push(
+                    iced::widget::slider(
+                        processing::FILMIC_BLACK_RANGE,
+                        settings.filmic_black,
+                        Message::FilmicBlack,
+                    )
+                    .step(0.001)
+                    .width(200)
+                    .on_release(Message::SaveSidecar),
+                );
+        }
+        row.into()
+    }
+
+    fn color_controls(&self) -> Element<'_, Message> {
+        let settings = &self.program.settings;
+        let band = settings.hsl.get(self.hue_band);
+        iced::widget::row![
+            iced::widget::slider(
+                hsl::SATURATION_RANGE,
+                settings.saturation,
+                Message::Saturation
+            )
+            .step(0.01)
+            .width(120)
+            .on_release(Message::SaveSidecar),
+            iced::widget::slider(hsl::VIBRANCE_RANGE, settings.vibrance, Message::Vibrance)
+                .step(0.01)
+                .width(120)
+                .on_release(Message::SaveSidecar),
+            iced::widget::pick_list(HueBand::ALL, Some(self.hue_band), Message::HueBand),
+            iced::widget::slider(hsl::HUE_RANGE, band.hue, Message::BandHue)
+                .step(0.5)
+                .width(120)
+                .on_release(Message::SaveSidecar),
+            iced::widget::slider(
+                hsl::SATURATION_RANGE,
+                band.saturation,
+                Message::BandSaturation
+            )
+            .step(0.01)
+            .width(120)
+            .on_release(Message::SaveSidecar),
+            iced::widget::slider(hsl::LUMINANCE_RANGE, band.luminance, Message::BandLuminance)
+                .step(0.01)
+                .width(120)
+                .on_release(Message::SaveSidecar),
+        ]
+        .spacing(20)
+        .align_y(iced::Alignment::Center)
+        .into()
+    }
+
+    fn lut_controls(&self) -> Element<'_, Message> {
+        let settings = &self.program.settings;
+        let Some(path) = &settings.lut else {
+            return iced::widget::button("Load LUT...")
+                .on_press(Message::LutDialog)
+                .into();
+        };
+        let name = path
+            .file_name()
+            .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy());
+        iced::widget::row![
+            iced::widget::button(iced::widget::text(name)).on_press(Message::LutDialog),
+            iced::widget::button("Remove").on_press(Message::RemoveLut),
+            iced::widget::pick_list(
+                LutInterpolation::ALL,
+                Some(settings.lut_interpolation),
+                Message::LutInterpolation
+            ),
+            iced::widget::slider(0.0..=1.0, settings.lut_intensity, Message::LutIntensity)
+                .step(0.01)
+                .width(200)
+                .on_release(Message::SaveSidecar)
+        ]
+        .spacing(20)
+        .align_y(iced::Alignment::Center)
+        .into()
+    }
+
+    fn curve_controls(&self) -> Element<'_, Message> {
+        let curve = self.program.settings.curves.get(self.curve_channel);
+        let mut reset = iced::widget::button("Reset");
+        if !curve.is_identity() {
+            reset = reset.on_press(Message::ResetCurve);
+        }
+        iced::widget::column![
+            iced::widget::row![
+                iced::widget::pick_list(
+                    CurveChannel::ALL,
+                    Some(self.curve_channel),
+                    Message::CurveChannel
+                ),
+                reset
+            ]
+            .spacing(5),
+            iced::widget::canvas(CurveEditor {
+                curve,
+                channel: self.curve_channel,
+            })
+            .width(curve_editor::SIZE.width)
+            .height(curve_editor::SIZE.height)
+        ]
+        .spacing(5)
+        .into()
+    }
+
+    fn white_balance_controls(&self) -> Element<'_, Message> {
+        let (temperature, tint) = self.program.temperature_tint();
+        let pick_label = if self.picking_white {
+            "Cancel"
+        } else {
+            "Pick white"
+        };
+        iced::widget::row![
+            iced::widget::pick_list(
+                WhiteBalance::ALL,
+                Some(self.program.settings.white_balance),
+                Message::WhiteBalance
+            ),
+            iced::widget::slider(
+                white_balance::TEMPERATURE_RANGE,
+                temperature,
+                Message::Temperature
+            )
+            .step(10.0)
+            .width(200)
+            .on_release(Message::SaveSidecar),
+            iced::widget::slider(white_balance::TINT_RANGE, tint, Message::Tint)
+                .step(1.0)
+                .width(200)
+                .on_release(Message::SaveSidecar),
+            iced::widget::button(pick_label).on_press(Message::PickWhite),
+        ]
+        .spacing(20)
+        .align_y(iced::Alignment::Center)
+        .into()
+    }
+
     pub fn footer_view(&self) -> Element<'_, Message> {
         iced::widget::container(
             iced::widget::row![
-                Self::image_buttons(),
+                iced::widget::button("Open...").on_press(Message::OpenFileDialog),
+                iced::widget::toggler(self.show_metadata)
+                    .label("Info")
+                    .on_toggle(Message::ShowMetadata),
                 iced::widget::text(format!(
                     "Image size: {}x{}, Window size: {}x{}\nUpdate time: {:.2?}",
                     self.program.image_size.width,
@@ -89,6 +516,10 @@ impl Ui {
                 ))
                 .size(10)
                 .color(iced::Color::WHITE),
+                self.export_view(),
+                self.loading_view(),
+                self.notice_view(),
+                self.error_view(),
             ]
             .spacing(10),
         )
@@ -101,40 +532,102 @@ impl Ui {
         .into()
     }
 
-    fn image_buttons<'a>() -> Option<Element<'a, Message>> {
+    fn export_view(&self) -> Element<'_, Message> {
+        let settings = self.export_settings;
+        iced::widget::row![
+            iced::widget::pick_list(
+                BitDepth::ALL,
+                Some(settings.bit_depth),
+                Message::ExportBitDepth
+            ),
+            iced::widget::column![
+                iced::widget::text(format!("JPEG quality: {}", settings.jpeg_quality))
+                    .size(10)
+                    .color(iced::Color::WHITE),
+                iced::widget::slider(1..=100, settings.jpeg_quality, Message::JpegQuality)
+                    .width(100),
+            ],
+            iced::widget::button("Export...")
+                .on_press_maybe(self.can_export().then_some(Message::ExportDialog)),
+        ]
+        .spacing(10)
+        .into()
+    }
+
+    fn loading_view(&self) -> Option<Element<'_, Message>> {
+        let loading = self.loading.as_ref()?;
+        let file_name = loading.path.file_name()?.to_string_lossy();
         Some(
-            iced::widget::container(
-                // list files in assets/ directory with .jpg or .CR2 extension
-                iced::widget::row(
-                    std::fs::read_dir("assets/")
-                        .ok()?
-                        .filter_map(std::result::Result::ok)
-                        .filter(|entry| {
-                            entry
-                                .path()
-                                .extension()
-                                .and_then(|ext| ext.to_str())
-                                .is_some_and(|ext| {
-                                    ext.eq_ignore_ascii_case("jpg")
-                                        || ext.eq_ignore_ascii_case("cr2")
-                                })
-                        })
-                        .map(|entry| {
-                            let path = entry.path();
-                            let file_name = path.file_name().and_then(|n| n.to_str()).map_or_else(
-                                || "Unknown".to_string(),
-                                std::string::ToString::to_string,
-                            );
-                            iced::widget::button::Button::new(iced::widget::text(file_name))
-                                .on_press(Message::LoadImage(path))
-                                .into()
-                        }),
-                ),
-            )
+            iced::widget::column![
+                iced::widget::text(format!("{}: {file_name}", loading.stage))
+                    .size(10)
+                    .color(iced::Color::WHITE),
+                iced::widget::progress_bar(0.0..=1.0, loading.progress)
+                    .length(150)
+                    .girth(6),
+            ]
+            .spacing(4)
             .into(),
         )
     }
 
+    fn notice_view(&self) -> Option<Element<'_, Message>> {
+        self.notice.as_ref().map(|notice| {
+            iced::widget::text(notice)
+                .size(10)
+                .color(iced::Color::WHITE)
+                .into()
+        })
+    }
+
+    fn error_view(&self) -> Option<Element<'_, Message>> {
+        self.error.as_ref().map(|error| {
+            iced::widget::text(error)
+                .size(10)
+                .color(iced::Color::from_rgb(1.0, 0.3, 0.3))
+                .into()
+        })
+    }
+
+    fn filmstrip_view(&self) -> Element<'_, Message> {
+        let items = self.files.iter().map(|path| {
+            let file_name = path
+                .file_name()
+                .map_or_else(|| "Unknown".into(), |name| name.to_string_lossy());
+            let thumbnail: Element<'_, Message> = self.thumbnails.get(path).map_or_else(
+                || {
+                    iced::widget::container(iced::widget::text("...").size(10))
+                        .center(FILMSTRIP_THUMBNAIL_SIZE)
+                        .into()
+                },
+                |handle| {
+                    iced::widget::image(handle.clone())
+                        .width(FILMSTRIP_THUMBNAIL_SIZE)
+                        .height(FILMSTRIP_THUMBNAIL_SIZE)
+                        .into()
+                },
+            );
+            let style = if *path == self.program.image_path {
+                iced::widget::button::primary
+            } else {
+                iced::widget::button::secondary
+            };
+            iced::widget::button(
+                iced::widget::column![thumbnail, iced::widget::text(file_name).size(10)]
+                    .spacing(2)
+                    .align_x(iced::Alignment::Center),
+            )
+            .style(style)
+            .on_press(Message::LoadImage(path.clone()))
+            .into()
+        });
+        iced::widget::scrollable(iced::widget::row(items).spacing(6).padding(4))
+            .direction(iced::widget::scrollable::Direction::Horizontal(
+                iced::widget::scrollable::Scrollbar::default(),
+            ))
+            .into()
+    }
+
     /*                iced::widget::button("IMG_6637.CR2")
                        .on_press(Message::LoadImage("assets/IMG_6637.CR2".into())),
                    iced::widget::button("IMG_6647.CR2")
@@ -172,49 +665,494 @@ impl Ui {
         }
     }
 
-    pub fn update(&mut self, message: Message) {
+    // One arm per message, which is how iced dispatches them
+    #[allow(clippy::cognitive_complexity, clippy::too_many_lines)]
+    pub fn update(&mut self, message: Message) -> Task<Message> {
         self.update_elapsed();
         match message {
-            Message::LoadImage(path) => self.load_image(&path),
+            Message::Open(path) => return self.open(path),
+            Message::OpenFileDialog => {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .set_title("Open image")
+                    .pick_file();
+                return Task::perform(dialog, |file| {
+                    Message::Picked(file.map(|file| file.path().to_path_buf()))
+                });
+            }
+            Message::OpenFolderDialog => {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .set_title("Open folder")
+                    .pick_folder();
+                return Task::perform(dialog, |folder| {
+                    Message::Picked(folder.map(|folder| folder.path().to_path_buf()))
+                });
+            }
+            Message::Picked(path) => {
+                if let Some(path) = path {
+                    return self.open(path);
+                }
+            }
+            Message::LoadImage(path) => return self.load_image(path),
+            Message::FolderListed(folder, listing, open_first) => {
+                return self.on_folder_listed(&folder, listing, open_first);
+            }
+            Message::Thumbnail(path, handle) => {
+                self.thumbnails.insert(path, handle);
+            }
+            Message::Loaded(event) => return self.on_load_event(event),
+            Message::Prefetched(path, result) => match result {
+                Ok(decoded) => {
+                    if let Some(modified) = cache::modified(&path) {
+                        self.cache.insert(modified, decoded);
+                    }
+                }
+                Err(e) => debug!("Error prefetching {path:?}: {e}"),
+            },
             Message::UpdateImage => {}
             Message::MouseMoved(position) => {
                 self.program.mouse_pos = (position.x, position.y);
             }
+            Message::MousePressed => {
+                if self.picking_white {
+                    self.pick_white();
+                }
+            }
             Message::MouseScrolled(delta) => {
                 self.program.scroll_delta += match delta {
                     iced::mouse::ScrollDelta::Lines { x: _, y } => y * 10.0,
                     iced::mouse::ScrollDelta::Pixels { x: _, y } => y,
                 };
             }
-            Message::WindowEvent(event) => self.process_window_event(&event),
+            Message::WindowEvent(event) => return self.process_window_event(event),
             Message::Exposure(value) => {
-                self.program.exposure = value;
+                self.program.settings.exposure = value;
             }
             Message::Contrast(value) => {
-                self.program.contrast = value;
+                self.program.settings.contrast = value;
+            }
+            Message::DemosaicAlgorithm(algorithm) => {
+                self.program.settings.demosaic_algorithm = algorithm;
+                self.save_sidecar();
+            }
+            Message::HighlightRecovery(recovery) => {
+                self.program.settings.highlight_recovery = recovery;
+                self.save_sidecar();
+            }
+            Message::ToneMapping(tone_mapping) => {
+                self.program.settings.tone_mapping = tone_mapping;
+                self.save_sidecar();
+            }
+            Message::FilmicWhite(value) => {
+                self.program.settings.filmic_white = value;
+            }
+            Message::FilmicBlack(value) => {
+                self.program.settings.filmic_black = value;
+            }
+            Message::CurveChannel(channel) => {
+                self.curve_channel = channel;
+            }
+            Message::Curve(curve) => {
+                *self.program.settings.curves.get_mut(self.curve_channel) = curve;
+            }
+            Message::ResetCurve => {
+                *self.program.settings.curves.get_mut(self.curve_channel) = Curve::default();
+                self.save_sidecar();
+            }
+            Message::Saturation(value) => {
+                self.program.settings.saturation = value;
+            }
+            Message::Vibrance(value) => {
+                self.program.settings.vibrance = value;
+            }
+            Message::HueBand(band) => {
+                self.hue_band = band;
+            }
+            Message::BandHue(value) => {
+                self.program.settings.hsl.get_mut(self.hue_band).hue = value;
+            }
+            Message::BandSaturation(value) => {
+                self.program.settings.hsl.get_mut(self.hue_band).saturation = value;
+            }
+            Message::BandLuminance(value) => {
+                self.program.settings.hsl.get_mut(self.hue_band).luminance = value;
+            }
+            Message::LutDialog => {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .set_title("Load LUT")
+                    .add_filter("Cube LUT", &["cube"])
+                    .pick_file();
+                return Task::perform(dialog, |file| {
+                    Message::LutPicked(file.map(|file| file.path().to_path_buf()))
+                });
+            }
+            Message::LutPicked(path) => {
+                if let Some(path) = path {
+                    self.set_lut(Some(path));
+                }
+            }
+            Message::RemoveLut => self.set_lut(None),
+            Message::LutIntensity(value) => {
+                self.program.settings.lut_intensity = value;
+            }
+            Message::LutInterpolation(interpolation) => {
+                self.program.settings.lut_interpolation = interpolation;
+                self.save_sidecar();
+            }
+            Message::OutputColorSpace(space) => {
+                self.program.settings.output_color_space = space;
+                self.save_sidecar();
+            }
+            Message::WhiteBalance(balance) => {
+                self.program.settings.white_balance = balance;
+                self.save_sidecar();
+            }
+            Message::Temperature(value) => {
+                let (_, tint) = self.program.temperature_tint();
+                self.set_custom_white_balance(value, tint);
+            }
+            Message::Tint(value) => {
+                let (temperature, _) = self.program.temperature_tint();
+                self.set_custom_white_balance(temperature, value);
+            }
+            Message::PickWhite => {
+                self.picking_white = !self.picking_white;
+            }
+            Message::SaveSidecar => self.save_sidecar(),
+            Message::ShowMetadata(show) => {
+                self.show_metadata = show;
+            }
+            Message::ExportDialog => return self.export_dialog(),
+            Message::ExportPicked(path) => {
+                if let Some(path) = path {
+                    return self.export(path);
+                }
+            }
+            Message::Exported(Exported { headless, result }) => {
+                self.exporting = false;
+                if headless.is_some() {
+                    self.export_device = headless;
+                }
+                match result {
+                    Ok(path) => {
+                        info!("Exported {path:?}");
+                        self.notice = Some(format!("Exported {}", path.display()));
+                        self.error = None;
+                    }
+                    Err(e) => {
+                        error!("Error exporting: {e}");
+                        self.notice = None;
+                        self.error = Some(e);
+                    }
+                }
+            }
+            Message::ExportBitDepth(bit_depth) => {
+                self.export_settings.bit_depth = bit_depth;
+            }
+            Message::JpegQuality(quality) => {
+                self.export_settings.jpeg_quality = quality;
+            }
+        }
+        Task::none()
+    }
+
+    /// Moving a slider starts from the white balance that was shown
+    const fn set_custom_white_balance(&mut self, temperature: f32, tint: f32) {
+        let settings = &mut self.program.settings;
+        settings.white_balance = WhiteBalance::Custom;
+        settings.temperature = temperature;
+        settings.tint = tint;
+    }
+
+    fn pick_white(&mut self) {
+        self.picking_white = false;
+        let display_size = self.image_display_size().to_f32();
+        match self.program.pick_white(display_size) {
+            Ok(()) => {
+                self.error = None;
+                self.save_sidecar();
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn export_dialog(&self) -> Task<Message> {
+        let stem = self
+            .program
+            .image_path
+            .file_stem()
+            .map_or_else(|| "export".into(), |stem| stem.to_string_lossy());
+        let mut dialog = rfd::AsyncFileDialog::new()
+            .set_title("Export image")
+            .set_file_name(format!("{stem}.jpg"));
+        if let Some(folder) = &self.folder {
+            dialog = dialog.set_directory(folder);
+        }
+        for format in ExportFormat::ALL {
+            dialog = dialog.add_filter(format.to_string(), format.extensions());
+        }
+        Task::perform(dialog.save_file(), |file| {
+            Message::ExportPicked(file.map(|file| file.path().to_path_buf()))
+        })
+    }
+
+    /// The embedded preview of a raw is only a stand-in until the raw is
+    /// decoded, so exporting it would miss most of the resolution and range
+    fn can_export(&self) -> bool {
+        !self.exporting && !self.program.image.is_preview()
+    }
+
+    /// Processes the current image at full resolution in the background
+    fn export(&mut self, path: PathBuf) -> Task<Message> {
+        // The image may have changed while the dialog was open
+        if !self.can_export() {
+            self.error = Some("Wait for the raw to finish loading before exporting".to_string());
+            return Task::none();
+        }
+        let Some(format) = ExportFormat::from_path(&path) else {
+            self.error = Some(format!("Unknown export format: {}", path.display()));
+            return Task::none();
+        };
+        let options = ExportOptions {
+            path,
+            format,
+            settings: self.export_settings,
+        };
+        self.exporting = true;
+        self.notice = Some("Exporting...".to_string());
+        Task::perform(
+            export::export_in_background(self.export_device.clone(), self.program.clone(), options),
+            Message::Exported,
+        )
+    }
+
+    /// Opens an image and browses its folder, or browses a folder starting
+    /// at its first image.
+    fn open(&mut self, path: PathBuf) -> Task<Message> {
+        // The listed files are in the folder of the opened path, so they
+        // only compare equal to it if both are absolute
+        let path = std::path::absolute(&path).unwrap_or(path);
+        if path.is_dir() {
+            self.set_folder(path);
+            return self.list_folder(true);
+        }
+        self.set_folder(loader::parent_folder(&path).to_path_buf());
+        Task::batch([self.list_folder(false), self.load_image(path)])
+    }
+
+    fn set_folder(&mut self, folder: PathBuf) {
+        if self.folder.as_ref() != Some(&folder) {
+            self.files.clear();
+            self.folder_modified = None;
+            self.thumbnails.clear();
+            self.folder = Some(folder);
+        }
+    }
+
+    /// Lists the current folder in the background, unless it hasn't changed
+    /// since the last listing
+    fn list_folder(&self, open_first: bool) -> Task<Message> {
+        let Some(folder) = self.folder.clone() else {
+            return Task::none();
+        };
+        let listed = if open_first {
+            None
+        } else {
+            self.folder_modified
+        };
+        Task::perform(
+            loader::list_folder(folder, listed),
+            move |(folder, listing)| Message::FolderListed(folder, listing, open_first),
+        )
+    }
+
+    fn on_folder_listed(
+        &mut self,
+        folder: &Path,
+        listing: Option<loader::Listing>,
+        open_first: bool,
+    ) -> Task<Message> {
+        // The user has moved on to another folder in the meantime, or the
+        // folder is unchanged
+        let Some(listing) = listing.filter(|_| self.folder.as_deref() == Some(folder)) else {
+            return Task::none();
+        };
+        self.folder_modified = listing.modified;
+        self.files = listing.files;
+        self.thumbnails.retain(|path, _| self.files.contains(path));
+        let missing: Vec<_> = self
+            .files
+            .iter()
+            .filter(|path| !self.thumbnails.contains_key(*path))
+            .cloned()
+            .collect();
+        if let Some(task) = self.thumbnail_task.take() {
+            task.abort();
+        }
+        let (thumbnails, handle) = Task::run(thumbnail::generate(missing), |(path, handle)| {
+            Message::Thumbnail(path, handle)
+        })
+        .abortable();
+        self.thumbnail_task = Some(handle);
+
+        let next = if open_first {
+            if let Some(first) = self.files.first().cloned() {
+                self.load_image(first)
+            } else {
+                self.error = Some(format!("No supported images in {}", folder.display()));
+                Task::none()
             }
+        } else if self.loading.is_none() {
+            // Otherwise the prefetch starts once the image has loaded
+            let current = self.program.image_path.clone();
+            self.prefetch_neighbours(&current)
+        } else {
+            Task::none()
+        };
+        Task::batch([thumbnails, next])
+    }
+
+    fn show(&mut self, decoded: &Decoded) {
+        self.program.set_image(decoded);
+        self.sidecar = decoded.sidecar.clone().unwrap_or_default();
+        self.metadata.clone_from(&decoded.metadata);
+    }
+
+    #[allow(clippy::cognitive_complexity)]
+    fn set_lut(&mut self, path: Option<PathBuf>) {
+        if let Err(e) = self.program.set_lut(path) {
+            error!("Could not load the LUT: {e}");
+            self.error = Some(format!("Could not load the LUT: {e}"));
+            return;
         }
+        self.save_sidecar();
     }
 
+    /// Writes the edits of the current image next to it, keeping whatever
+    /// else its sidecar holds.
     #[allow(clippy::cognitive_complexity)]
-    fn load_image(&mut self, path: &Path) {
-        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
-            match extension {
-                "cr2" | "CR2" => {
-                    if let Err(e) = self.program.load_cr2_image(path) {
-                        error!("Error loading CR2 image from {path:?}: {e}");
-                    }
+    fn save_sidecar(&mut self) {
+        let path = self.program.image_path.clone();
+        let unchanged = self.sidecar.settings().ok().as_ref() == Some(&self.program.settings);
+        if path.as_os_str().is_empty() || unchanged {
+            return;
+        }
+        let result = self
+            .sidecar
+            .set_settings(&self.program.settings)
+            .and_then(|()| self.sidecar.save(&path));
+        if let Err(e) = result {
+            error!("Could not save the edits of {path:?}: {e}");
+            self.error = Some(format!("Could not save the edits: {e}"));
+            return;
+        }
+        // Coming back to the image from the cache has to show the new edits
+        if let Some(modified) = cache::modified(&path)
+            && let Some(decoded) = self.cache.get(&path, modified)
+        {
+            let decoded = Decoded {
+                sidecar: Some(self.sidecar.clone()),
+                ..(*decoded).clone()
+            };
+            self.cache.insert(modified, Arc::new(decoded));
+        }
+    }
+
+    /// Starts decoding the image in the background, cancelling any load
+    /// that is still in progress. Cached images are shown right away.
+    fn load_image(&mut self, path: PathBuf) -> Task<Message> {
+        if let Some(loading) = self.loading.take() {
+            loading.cancel.cancel();
+            loading.handle.abort();
+        }
+        if let Some(decoded) = cache::modified(&path).and_then(|m| self.cache.get(&path, m)) {
+            self.show(&decoded);
+            self.error = None;
+            return self.prefetch_neighbours(&path);
+        }
+        let cancel = loader::Cancel::default();
+        let (task, handle) =
+            Task::run(loader::load(path.clone(), cancel.clone()), Message::Loaded).abortable();
+        self.loading = Some(Loading {
+            path,
+            stage: LoadStage::Detecting,
+            progress: 0.0,
+            handle,
+            cancel,
+        });
+        task
+    }
+
+    /// Decodes the files before and after `path` in its folder into the
+    /// cache, so stepping through the folder doesn't wait for the decoder.
+    fn prefetch_neighbours(&mut self, path: &Path) -> Task<Message> {
+        let Some(index) = self.files.iter().position(|p| p == path) else {
+            return Task::none();
+        };
+        let neighbours = [index.checked_sub(1), index.checked_add(1)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.files.get(i))
+            .map(|p| (p.clone(), self.cache.modified(p)))
+            .collect();
+        let mut task = Task::none();
+        let prefetcher = self.prefetcher.get_or_insert_with(|| {
+            let (prefetcher, decoded) = loader::Prefetcher::start();
+            task = Task::run(decoded, |(file, result)| Message::Prefetched(file, result));
+            prefetcher
+        });
+        prefetcher.request(neighbours);
+        task
+    }
+
+    #[allow(clippy::cognitive_complexity)]
+    fn on_load_event(&mut self, event: LoadEvent) -> Task<Message> {
+        match event {
+            LoadEvent::Progress(path, stage, progress) => {
+                if let Some(loading) = self.loading.as_mut().filter(|l| l.path == path) {
+                    loading.stage = stage;
+                    loading.progress = progress;
                 }
-                "jpg" | "JPG" => {
-                    if let Err(e) = self.program.load_image(path) {
+            }
+            LoadEvent::Preview(preview) => {
+                if self
+                    .loading
+                    .as_ref()
+                    .is_some_and(|l| l.path == preview.path)
+                {
+                    self.show(&preview);
+                }
+            }
+            LoadEvent::Done(path, result) => {
+                // Ignore whatever a cancelled load managed to send
+                if self.loading.as_ref().is_none_or(|l| l.path != path) {
+                    return Task::none();
+                }
+                self.loading = None;
+                match result {
+                    Ok(mut decoded) => {
+                        // Edits made while the preview was showing are newer
+                        // than the sidecar the decoder read
+                        if path == self.program.image_path {
+                            decoded = Arc::new(Decoded {
+                                sidecar: Some(self.sidecar.clone()),
+                                ..(*decoded).clone()
+                            });
+                        }
+                        self.show(&decoded);
+                        self.error = None;
+                        if let Some(modified) = cache::modified(&path) {
+                            self.cache.insert(modified, decoded);
+                        }
+                        return self.prefetch_neighbours(&path);
+                    }
+                    Err(e) => {
                         error!("Error loading image from {path:?}: {e}");
+                        self.error = Some(e);
                     }
                 }
-                _ => {
-                    error!("Unsupported image format: {}", extension);
-                }
             }
         }
+        Task::none()
     }
 
     fn update_elapsed(&mut self) {
@@ -223,11 +1161,23 @@ impl Ui {
         self.program.last_iteration = Instant::now();
     }
 
-    const fn process_window_event(&mut self, event: &iced::window::Event) {
+    fn process_window_event(&mut self, event: iced::window::Event) -> Task<Message> {
         // info!("Window event received: {event:?}");
         match event {
             iced::window::Event::Resized(size) => {
-                self.window_size = *size;
+                self.window_size = size;
+            }
+            // Pick up files that were added or removed while we were away
+            iced::window::Event::Focused => return self.list_folder(false),
+            iced::window::Event::FileHovered(_) => {
+                self.hovering = true;
+            }
+            iced::window::Event::FilesHoveredLeft => {
+                self.hovering = false;
+            }
+            iced::window::Event::FileDropped(path) => {
+                self.hovering = false;
+                return self.open_dropped(path);
             }
             iced::window::Event::Opened {
                 position: _,
@@ -237,11 +1187,21 @@ impl Ui {
             | iced::window::Event::Moved(_)
             | iced::window::Event::RedrawRequested(_)
             | iced::window::Event::CloseRequested
-            | iced::window::Event::Focused
-            | iced::window::Event::Unfocused
-            | iced::window::Event::FileHovered(_)
-            | iced::window::Event::FileDropped(_)
-            | iced::window::Event::FilesHoveredLeft => {}
+            | iced::window::Event::Unfocused => {}
+        }
+        Task::none()
+    }
+
+    /// Dropped folders are opened as is, files only if we can decode them
+    #[allow(clippy::cognitive_complexity)]
+    fn open_dropped(&mut self, path: PathBuf) -> Task<Message> {
+        if !path.is_dir()
+            && let Err(e) = format::detect(&path)
+        {
+            error!("Cannot open dropped file {path:?}: {e}");
+            self.error = Some(e.to_string());
+            return Task::none();
         }
+        self.open(path)
     }
 }
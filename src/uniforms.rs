@@ -1,3 +1,10 @@
+use crate::{
+    color::Transfer,
+    compute::processing::{HighlightRecovery, LutInterpolation, ToneMapping},
+    hsl::Hsl,
+    orientation::Orientation,
+};
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Uniforms {
     pub mouse_pos: (f32, f32),
@@ -5,19 +12,65 @@ pub struct Uniforms {
     pub window_size: iced::Size<f32>,
     pub image_size: iced::Size<f32>,
     pub cam_2_xyz: [[f32; 4]; 3],
-    pub xyz_2_srgb: [[f32; 3]; 3],
+    pub xyz_2_output: [[f32; 3]; 3],
+    /// Linear output RGB to linear sRGB, for the window
+    pub output_2_display: [[f32; 3]; 3],
     pub whitelevels: [f32; 4],
     pub blacklevels: [f32; 4],
     pub crops: [u32; 4],
     pub exposure: f32,
     pub contrast: f32,
+    pub cfa: [[u32; 6]; 6],
+    pub cfa_size: u32,
+    pub orientation: Orientation,
+    pub wb_multipliers: [f32; 4],
+    pub transfer: Transfer,
+    pub input_kind: InputKind,
+    pub highlight_recovery: HighlightRecovery,
+    pub tone_mapping: ToneMapping,
+    pub filmic_white: f32,
+    pub filmic_black: f32,
+    /// Entries along each axis of the 3D LUT, 0 without one
+    pub lut_size: u32,
+    pub lut_domain_min: [f32; 3],
+    pub lut_domain_max: [f32; 3],
+    pub lut_intensity: f32,
+    pub lut_interpolation: LutInterpolation,
+    pub hsl: Hsl,
+    pub saturation: f32,
+    pub vibrance: f32,
+}
+
+/// What the pixels of the full size texture hold, which decides how the
+/// shaders treat them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    /// Sensor values that still have to be demosaiced
+    #[default]
+    Raw,
+    /// Display ready sRGB, which is linearised before processing
+    Srgb,
+    /// Scene-linear RGB with the sRGB primaries, which may exceed 1
+    Linear,
+}
+
+impl InputKind {
+    /// The `INPUT_` constant of the shaders
+    pub const fn to_shader(self) -> u32 {
+        match self {
+            Self::Raw => 0,
+            Self::Srgb => 1,
+            Self::Linear => 2,
+        }
+    }
 }
 
 impl Uniforms {
     pub fn to_raw(self, output_size: iced::Size<f32>) -> Raw {
         Raw {
             cam_2_xyz: self.cam_2_xyz,
-            xyz_2_srgb: pad_matrix(self.xyz_2_srgb),
+            xyz_2_output: pad_matrix(self.xyz_2_output),
+            output_2_display: pad_matrix(self.output_2_display),
             whitelevels: self.whitelevels,
             blacklevels: self.blacklevels,
             crops: self.crops,
@@ -28,12 +81,53 @@ impl Uniforms {
             output_size: output_size.into(),
             exposure: self.exposure,
             contrast: self.contrast,
-            _padding: [0.0; 1],
-            _pad2: [0.0; 4],
+            cfa_size: self.cfa_size,
+            cfa: pack_cfa(self.cfa),
+            orientation: self.orientation.to_exif(),
+            _padding: [0; 3],
+            wb_multipliers: self.wb_multipliers,
+            transfer: self.transfer.to_shader(),
+            input_kind: self.input_kind.to_shader(),
+            highlight_recovery: self.highlight_recovery.to_shader(),
+            tone_mapping: self.tone_mapping.to_shader(),
+            filmic_white: self.filmic_white,
+            filmic_black: self.filmic_black,
+            lut_intensity: self.lut_intensity,
+            lut_interpolation: self.lut_interpolation.to_shader(),
+            lut_domain_min: self.lut_domain_min,
+            lut_size: self.lut_size,
+            lut_domain_max: self.lut_domain_max,
+            _padding2: 0,
+            hsl_hue: pack_bands(self.hsl.bands(|band| band.hue)),
+            hsl_saturation: pack_bands(self.hsl.bands(|band| band.saturation)),
+            hsl_luminance: pack_bands(self.hsl.bands(|band| band.luminance)),
+            saturation: self.saturation,
+            vibrance: self.vibrance,
+            _padding3: [0; 2],
         }
     }
 }
 
+/// Tiles a 2x2 Bayer pattern, given in row-major order, to the 6x6 CFA
+/// layout shared with X-Trans sensors.
+pub fn tile_cfa(pattern: [u32; 4]) -> [[u32; 6]; 6] {
+    std::array::from_fn(|y| std::array::from_fn(|x| pattern[y % 2 * 2 + x % 2]))
+}
+
+/// Packs the CFA into vec4s, as uniform arrays need a 16 byte stride.
+fn pack_cfa(cfa: [[u32; 6]; 6]) -> [[u32; 4]; 9] {
+    let flat = cfa.as_flattened();
+    std::array::from_fn(|i| std::array::from_fn(|j| flat[i * 4 + j]))
+}
+
+/// Packs a value per hue band into vec4s, for the same reason as the CFA
+const fn pack_bands(bands: [f32; 8]) -> [[f32; 4]; 2] {
+    [
+        [bands[0], bands[1], bands[2], bands[3]],
+        [bands[4], bands[5], bands[6], bands[7]],
+    ]
+}
+
 const fn pad_matrix(matrix: [[f32; 3]; 3]) -> [[f32; 4]; 3] {
     [
         [matrix[0][0], matrix[0][1], matrix[0][2], 0.0],
@@ -46,7 +140,8 @@ const fn pad_matrix(matrix: [[f32; 3]; 3]) -> [[f32; 4]; 3] {
 #[repr(C)]
 pub struct Raw {
     pub cam_2_xyz: [[f32; 4]; 3],
-    pub xyz_2_srgb: [[f32; 4]; 3],
+    pub xyz_2_output: [[f32; 4]; 3],
+    pub output_2_display: [[f32; 4]; 3],
     pub whitelevels: [f32; 4],
     pub blacklevels: [f32; 4],
     pub crops: [u32; 4],
@@ -57,6 +152,27 @@ pub struct Raw {
     pub scroll_delta: f32,
     pub exposure: f32,
     pub contrast: f32,
-    _padding: [f32; 1],
-    _pad2: [f32; 4],
+    pub cfa_size: u32,
+    pub cfa: [[u32; 4]; 9],
+    pub orientation: u32,
+    _padding: [u32; 3],
+    pub wb_multipliers: [f32; 4],
+    pub transfer: u32,
+    pub input_kind: u32,
+    pub highlight_recovery: u32,
+    pub tone_mapping: u32,
+    pub filmic_white: f32,
+    pub filmic_black: f32,
+    pub lut_intensity: f32,
+    pub lut_interpolation: u32,
+    pub lut_domain_min: [f32; 3],
+    pub lut_size: u32,
+    pub lut_domain_max: [f32; 3],
+    _padding2: u32,
+    pub hsl_hue: [[f32; 4]; 2],
+    pub hsl_saturation: [[f32; 4]; 2],
+    pub hsl_luminance: [[f32; 4]; 2],
+    pub saturation: f32,
+    pub vibrance: f32,
+    _padding3: [u32; 2],
 }
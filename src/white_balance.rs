@@ -0,0 +1,223 @@
+use std::ops::RangeInclusive;
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Which light the camera values are balanced for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhiteBalance {
+    /// The multipliers recorded by the camera
+    #[default]
+    #[display("As shot")]
+    AsShot,
+    Daylight,
+    Tungsten,
+    /// The temperature and tint of the settings
+    Custom,
+}
+
+impl WhiteBalance {
+    pub const ALL: [Self; 4] = [Self::AsShot, Self::Daylight, Self::Tungsten, Self::Custom];
+
+    /// Temperature and tint of the fixed presets
+    pub const fn preset(self) -> Option<(f32, f32)> {
+        match self {
+            Self::Daylight => Some((5500.0, 0.0)),
+            Self::Tungsten => Some((2850.0, 0.0)),
+            Self::AsShot | Self::Custom => None,
+        }
+    }
+}
+
+/// In kelvin
+pub const TEMPERATURE_RANGE: RangeInclusive<f32> = 2000.0..=12000.0;
+/// Positive tints are magenta, negative ones green
+pub const TINT_RANGE: RangeInclusive<f32> = -100.0..=100.0;
+/// Distance from the Planckian locus in CIE 1960 UCS of one unit of tint
+const TINT_SCALE: f64 = 1.0 / 3000.0;
+/// The white point that the camera matrix maps to white
+const D65: [f64; 3] = [0.950_47, 1.0, 1.088_83];
+
+/// Chromaticity in CIE 1960 UCS
+type Uv = (f64, f64);
+
+/// Multipliers for the camera values, applied before the camera matrix.
+/// Without as shot multipliers, `AsShot` falls back to the temperature and
+/// tint of the settings.
+pub fn multipliers(
+    raw: &rawloader::RawImage,
+    balance: WhiteBalance,
+    temperature: f32,
+    tint: f32,
+) -> [f32; 4] {
+    let as_shot = as_shot_gains(raw).filter(|_| balance == WhiteBalance::AsShot);
+    let gains = as_shot.unwrap_or_else(|| {
+        let (temperature, tint) = balance.preset().unwrap_or((temperature, tint));
+        neutral_gains(
+            &raw.xyz_to_cam,
+            white_point(f64::from(temperature), f64::from(tint)),
+        )
+    });
+    camera_multipliers(&raw.xyz_to_cam, gains)
+}
+
+/// Temperature and tint of the as shot multipliers, if the camera recorded
+/// usable ones
+pub fn as_shot(raw: &rawloader::RawImage) -> Option<(f32, f32)> {
+    let gains = as_shot_gains(raw)?;
+    Some(neutral_temperature(
+        raw,
+        gains.map(|gain| (1.0 / gain) as f32),
+    ))
+}
+
+/// Temperature and tint of the light that a neutral surface reflects, given
+/// the camera values it was recorded with
+pub fn neutral_temperature(raw: &rawloader::RawImage, neutral: [f32; 3]) -> (f32, f32) {
+    let cam_to_xyz = raw.cam_to_xyz();
+    // The fourth channel of four color sensors is a second green
+    let camera = [neutral[0], neutral[1], neutral[2], neutral[1]];
+    let xyz = std::array::from_fn(|i| {
+        cam_to_xyz[i]
+            .iter()
+            .zip(camera)
+            .map(|(&m, v)| f64::from(m * v))
+            .sum()
+    });
+    temperature_tint(xyz)
+}
+
+fn as_shot_gains(raw: &rawloader::RawImage) -> Option<[f64; 3]> {
+    let gains = [raw.wb_coeffs[0], raw.wb_coeffs[1], raw.wb_coeffs[2]].map(f64::from);
+    gains
+        .iter()
+        .all(|gain| gain.is_finite() && *gain > 0.0)
+        .then_some(gains)
+}
+
+/// Gains that make light of the given white point neutral
+fn neutral_gains(xyz_to_cam: &[[f32; 3]; 4], white: [f64; 3]) -> [f64; 3] {
+    camera_response(xyz_to_cam, white).map(|v| 1.0 / v)
+}
+
+/// The camera matrix maps values that are neutral under D65 to white, so the
+/// gains are scaled by the response to D65. Green is left unchanged.
+fn camera_multipliers(xyz_to_cam: &[[f32; 3]; 4], gains: [f64; 3]) -> [f32; 4] {
+    let d65 = camera_response(xyz_to_cam, D65);
+    let m: [f64; 3] = std::array::from_fn(|c| gains[c] * d65[c]);
+    if !m.iter().all(|v| v.is_finite() && *v > 0.0) {
+        return [1.0; 4];
+    }
+    [(m[0] / m[1]) as f32, 1.0, (m[2] / m[1]) as f32, 1.0]
+}
+
+/// Value of each camera channel for light of the given color
+fn camera_response(xyz_to_cam: &[[f32; 3]; 4], xyz: [f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|c| {
+        xyz_to_cam[c]
+            .iter()
+            .zip(xyz)
+            .map(|(&m, v)| f64::from(m) * v)
+            .sum()
+    })
+}
+
+/// Krystek's rational approximation of the Planckian locus, which holds
+/// from 1000 K to 15000 K
+fn planckian(temperature: f64) -> Uv {
+    let quadratic = |[a, b, c]: [f64; 3]| c.mul_add(temperature, b).mul_add(temperature, a);
+    let u = quadratic([0.860_117_757, 1.541_182_54e-4, 1.286_412_12e-7])
+        / quadratic([1.0, 8.424_202_35e-4, 7.081_451_63e-7]);
+    let v = quadratic([0.317_398_726, 4.228_062_45e-5, 4.204_816_91e-8])
+        / quadratic([1.0, -2.897_418_16e-5, 1.614_560_53e-7]);
+    (u, v)
+}
+
+/// Unit normal of the locus, pointing towards green
+fn green_normal(temperature: f64) -> Uv {
+    let (u0, v0) = planckian(temperature - 1.0);
+    let (u1, v1) = planckian(temperature + 1.0);
+    let (du, dv) = (u1 - u0, v1 - v0);
+    let length = du.hypot(dv);
+    (dv / length, -du / length)
+}
+
+/// XYZ, with Y = 1, of the white point with the given temperature and tint
+fn white_point(temperature: f64, tint: f64) -> [f64; 3] {
+    let (u, v) = planckian(temperature);
+    let (nu, nv) = green_normal(temperature);
+    let offset = -tint * TINT_SCALE;
+    uv_to_xyz((offset.mul_add(nu, u), offset.mul_add(nv, v)))
+}
+
+fn uv_to_xyz((u, v): Uv) -> [f64; 3] {
+    let denominator = 2.0_f64.mul_add(u, (-8.0_f64).mul_add(v, 4.0));
+    let (x, y) = (3.0 * u / denominator, 2.0 * v / denominator);
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+fn xyz_to_uv([x, y, z]: [f64; 3]) -> Uv {
+    let denominator = 3.0_f64.mul_add(z, 15.0_f64.mul_add(y, x));
+    (4.0 * x / denominator, 6.0 * y / denominator)
+}
+
+/// The temperature and tint whose white point is closest to the given one
+fn temperature_tint(xyz: [f64; 3]) -> (f32, f32) {
+    let (u, v) = xyz_to_uv(xyz);
+    let distance = |temperature: f64| {
+        let (pu, pv) = planckian(temperature);
+        (u - pu).hypot(v - pv)
+    };
+    // Searched in mired, in which the locus is close to evenly spaced
+    let mut low = 1e6 / f64::from(*TEMPERATURE_RANGE.end());
+    let mut high = 1e6 / f64::from(*TEMPERATURE_RANGE.start());
+    for _ in 0..100 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if distance(1e6 / a) < distance(1e6 / b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    let temperature = 2e6 / (low + high);
+    let (pu, pv) = planckian(temperature);
+    let (nu, nv) = green_normal(temperature);
+    let tint = -(u - pu).mul_add(nu, (v - pv) * nv) / TINT_SCALE;
+    (
+        temperature as f32,
+        (tint as f32).clamp(*TINT_RANGE.start(), *TINT_RANGE.end()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temperature_round_trip() {
+        for (temperature, tint) in [(2850.0, 0.0), (5500.0, 20.0), (9000.0, -35.0)] {
+            let (t, d) = temperature_tint(white_point(temperature, tint));
+            assert!(
+                (f64::from(t) - temperature).abs() < 1.0,
+                "{t} {temperature}"
+            );
+            assert!((f64::from(d) - tint).abs() < 0.1, "{d} {tint}");
+        }
+    }
+
+    #[test]
+    fn test_d65_neutral_keeps_camera_values() {
+        let xyz_to_cam = [
+            [0.6444, -0.0904, -0.0893],
+            [-0.4563, 1.2308, 0.2535],
+            [-0.0903, 0.1370, 0.5768],
+            [0.0; 3],
+        ];
+        let multipliers = camera_multipliers(&xyz_to_cam, neutral_gains(&xyz_to_cam, D65));
+        for m in multipliers {
+            assert!((m - 1.0).abs() < 1e-5, "{multipliers:?}");
+        }
+    }
+}